@@ -0,0 +1,182 @@
+//! Measures `on_event` with each storage backend, next to a nested dispatcher laid out as machines
+//! were before their transitions moved into one flat table
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkGroup, Criterion, Throughput};
+use criterion::measurement::WallTime;
+use fsm::{Action, DenseStorage, EnumTag, HashStorage, Machine, Predicate, SortedStorage, Storage};
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum LinkState {
+	Idle,
+	Connecting,
+	Connected,
+	Sending,
+	Receiving,
+	Closing,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum LinkEvent {
+	Dial,
+	Accept,
+	Send,
+	Sent,
+	Receive,
+	Received,
+	Close,
+	Closed,
+}
+
+impl EnumTag for LinkState {
+	fn tag_number(&self) -> usize {
+		*self as usize
+	}
+	fn max_tag_number() -> usize {
+		LinkState::Closing as usize
+	}
+}
+
+impl EnumTag for LinkEvent {
+	fn tag_number(&self) -> usize {
+		*self as usize
+	}
+	fn max_tag_number() -> usize {
+		LinkEvent::Closed as usize
+	}
+}
+
+const CYCLE: [LinkEvent; 8] = [
+	LinkEvent::Dial, LinkEvent::Accept, LinkEvent::Send, LinkEvent::Sent,
+	LinkEvent::Receive, LinkEvent::Received, LinkEvent::Close, LinkEvent::Closed,
+];
+
+fn link<'a, B: Storage>() -> Machine<'a, LinkState, LinkEvent, u64, B> {
+	let mut machine = Machine::with_storage(LinkState::Idle, 0);
+	machine.add_transition(LinkState::Idle, LinkEvent::Dial, LinkState::Connecting, |_,_| {});
+	machine.add_transition(LinkState::Connecting, LinkEvent::Accept, LinkState::Connected, |_,_| {});
+	machine.add_transition_with_context(LinkState::Connected, LinkEvent::Send, LinkState::Sending, |_, _, sent| *sent += 1);
+	machine.add_transition(LinkState::Sending, LinkEvent::Sent, LinkState::Connected, |_,_| {});
+	machine.add_guarded_transition_with_context(
+		LinkState::Connected, LinkEvent::Receive, LinkState::Receiving,
+		|_, _, sent| *sent > 0, |_,_,_| {}
+	);
+	machine.add_transition(LinkState::Receiving, LinkEvent::Received, LinkState::Connected, |_,_| {});
+	machine.add_transition(LinkState::Connected, LinkEvent::Close, LinkState::Closing, |_,_| {});
+	machine.add_transition(LinkState::Closing, LinkEvent::Closed, LinkState::Idle, |_,_| {});
+	machine
+}
+
+/// The NestedTransition is a transition of the Nested dispatcher
+struct NestedTransition {
+	next_state: LinkState,
+	predicate: Option<Predicate<'static, LinkState, LinkEvent, u64>>,
+	action: Action<'static, LinkState, LinkEvent, u64>,
+}
+
+/// The Nested dispatcher keeps, for each state, a Vec of the transitions for each event, as
+/// machines did before the flat table. It has none of a machine's hierarchy, queue or timers
+struct Nested {
+	state: LinkState,
+	context: u64,
+	states: Vec<Option<Vec<Vec<NestedTransition>>>>,
+}
+
+impl Nested {
+	fn new(state: LinkState) -> Nested {
+		Nested {
+			state,
+			context: 0,
+			states: (0..=LinkState::max_tag_number()).map(|_| None).collect(),
+		}
+	}
+
+	fn add<F>(&mut self, in_state: LinkState, on_event: LinkEvent, next_state: LinkState, predicate: Option<Predicate<'static, LinkState, LinkEvent, u64>>, action: F)
+	where F: FnMut(&LinkState, &LinkEvent, &mut u64) + 'static {
+		let edges = self.states[in_state.tag_number()]
+			.get_or_insert_with(|| (0..=LinkEvent::max_tag_number()).map(|_| Vec::new()).collect());
+		edges[on_event.tag_number()].push(NestedTransition {
+			next_state,
+			predicate,
+			action: Box::new(action),
+		});
+	}
+
+	fn on_event(&mut self, event: LinkEvent) -> bool {
+		let state = self.state;
+		let context = &mut self.context;
+		let candidates = match self.states[state.tag_number()] {
+			Some(ref mut edges) => &mut edges[event.tag_number()],
+			None => return false,
+		};
+		let taken = candidates.iter_mut().find(|t| match t.predicate {
+			Some(ref predicate) => predicate(&state, &event, context),
+			None => true,
+		});
+		match taken {
+			Some(transition) => {
+				(transition.action)(&state, &event, context);
+				self.state = transition.next_state;
+				true
+			},
+			None => false,
+		}
+	}
+}
+
+fn nested_link() -> Nested {
+	let mut nested = Nested::new(LinkState::Idle);
+	nested.add(LinkState::Idle, LinkEvent::Dial, LinkState::Connecting, None, |_,_,_| {});
+	nested.add(LinkState::Connecting, LinkEvent::Accept, LinkState::Connected, None, |_,_,_| {});
+	nested.add(LinkState::Connected, LinkEvent::Send, LinkState::Sending, None, |_, _, sent| *sent += 1);
+	nested.add(LinkState::Sending, LinkEvent::Sent, LinkState::Connected, None, |_,_,_| {});
+	nested.add(LinkState::Connected, LinkEvent::Receive, LinkState::Receiving, Some(Box::new(|_, _, sent| *sent > 0)), |_,_,_| {});
+	nested.add(LinkState::Receiving, LinkEvent::Received, LinkState::Connected, None, |_,_,_| {});
+	nested.add(LinkState::Connected, LinkEvent::Close, LinkState::Closing, None, |_,_,_| {});
+	nested.add(LinkState::Closing, LinkEvent::Closed, LinkState::Idle, None, |_,_,_| {});
+	nested
+}
+
+/// Benchmarks ticking with every event of the cycle, each followed by `unhandled` if given
+fn bench_cycle(group: &mut BenchmarkGroup<WallTime>, unhandled: Option<LinkEvent>) {
+	let per_event = if unhandled.is_some() { 2 } else { 1 };
+	group.throughput(Throughput::Elements((per_event * CYCLE.len()) as u64));
+	bench_storage::<DenseStorage>(group, "dense", unhandled);
+	bench_storage::<SortedStorage>(group, "sorted", unhandled);
+	bench_storage::<HashStorage>(group, "hash", unhandled);
+	group.bench_function("nested", |b| {
+		let mut nested = nested_link();
+		b.iter(|| {
+			for &event in &CYCLE {
+				black_box(nested.on_event(black_box(event)));
+				if let Some(event) = unhandled {
+					black_box(nested.on_event(black_box(event)));
+				}
+			}
+		})
+	});
+}
+
+fn bench_storage<B: Storage>(group: &mut BenchmarkGroup<WallTime>, storage: &str, unhandled: Option<LinkEvent>) {
+	group.bench_function(storage, |b| {
+		let mut machine = link::<B>();
+		b.iter(|| {
+			for &event in &CYCLE {
+				black_box(machine.on_event(black_box(event)));
+				if let Some(event) = unhandled {
+					black_box(machine.on_event(black_box(event)));
+				}
+			}
+		})
+	});
+}
+
+fn on_event(c: &mut Criterion) {
+	let mut group = c.benchmark_group("on_event/handled");
+	bench_cycle(&mut group, None);
+	group.finish();
+	let mut group = c.benchmark_group("on_event/unhandled");
+	bench_cycle(&mut group, Some(LinkEvent::Closed));
+	group.finish();
+}
+
+criterion_group!(benches, on_event);
+criterion_main!(benches);
@@ -0,0 +1,161 @@
+//! Provides `#[derive(EnumTag)]` for the `fsm` crate, enable the `derive` feature of `fsm` to use
+//! it through `fsm::EnumTag`.
+//!
+//! The derive works for any fieldless enum, including those with explicit discriminants:
+//!
+//! ```
+//! #[macro_use] extern crate fsm_derive;
+//! extern crate fsm;
+//!
+//! use fsm::EnumTag;
+//!
+//! #[derive(Copy, Clone, EnumTag)]
+//! enum Level {
+//!     Low = 1,
+//!     High = 10,
+//!     Medium = 5,
+//! }
+//!
+//! fn main() {
+//!     assert_eq!(Level::Medium.tag_number(), 5);
+//!     assert_eq!(Level::max_tag_number(), 10);
+//! }
+//! ```
+//!
+//! Enums whose variants carry data are rejected at compile time:
+//!
+//! ```compile_fail
+//! #[macro_use] extern crate fsm_derive;
+//! extern crate fsm;
+//!
+//! #[derive(Copy, Clone, EnumTag)]
+//! enum Message {
+//!     Ping,
+//!     Data(u8),
+//! }
+//!
+//! fn main() {}
+//! ```
+//!
+//! As are enums with negative discriminants, however they are written:
+//!
+//! ```compile_fail
+//! #[macro_use] extern crate fsm_derive;
+//! extern crate fsm;
+//!
+//! #[derive(Copy, Clone, EnumTag)]
+//! enum Offset {
+//!     Before = 0 - 1,
+//!     After = 1,
+//! }
+//!
+//! fn main() {}
+//! ```
+
+extern crate proc_macro;
+extern crate proc_macro2;
+#[macro_use]
+extern crate quote;
+extern crate syn;
+
+use proc_macro::TokenStream;
+use syn::{Data, DeriveInput, Error, Expr, Fields, UnOp};
+
+/// Derives `fsm::EnumTag` for a fieldless enum
+#[proc_macro_derive(EnumTag)]
+pub fn derive_enum_tag(input: TokenStream) -> TokenStream {
+	let input = syn::parse_macro_input!(input as DeriveInput);
+	match expand(&input) {
+		Ok(tokens) => tokens.into(),
+		Err(err) => compile_error(&err).into(),
+	}
+}
+
+/// Reports the error through the unqualified `compile_error!`, `Error::to_compile_error` names it
+/// through `::core` which is not in scope for 2015 edition crates
+fn compile_error(err: &Error) -> proc_macro2::TokenStream {
+	let message = err.to_string();
+	quote_spanned! { err.span() =>
+		compile_error!(#message);
+	}
+}
+
+fn expand(input: &DeriveInput) -> Result<proc_macro2::TokenStream, Error> {
+	let name = &input.ident;
+	let data = match input.data {
+		Data::Enum(ref data) => data,
+		_ => return Err(Error::new_spanned(input, "EnumTag can only be derived for enums")),
+	};
+
+	if data.variants.is_empty() {
+		return Err(Error::new_spanned(input, "EnumTag cannot be derived for an enum with no variants"));
+	}
+
+	let mut variants = Vec::with_capacity(data.variants.len());
+	let mut checks = Vec::new();
+	for variant in &data.variants {
+		match variant.fields {
+			Fields::Unit => {},
+			_ => return Err(Error::new_spanned(
+				variant,
+				"EnumTag can only be derived for fieldless enums, this variant carries data",
+			)),
+		}
+		if let Some((_, ref discriminant)) = variant.discriminant {
+			if let Expr::Unary(ref expr) = *discriminant {
+				if let UnOp::Neg(_) = expr.op {
+					return Err(Error::new_spanned(expr, "EnumTag requires non-negative discriminants"));
+				}
+			}
+			// any other expression may still evaluate to a negative value, which would wrap around to
+			// a huge tag, so it is checked once the compiler has evaluated it
+			let ident = &variant.ident;
+			checks.push(quote_spanned! { syn::spanned::Spanned::span(discriminant) =>
+				const _: () = assert!(#name::#ident as i128 >= 0, "EnumTag requires non-negative discriminants");
+			});
+		}
+		variants.push(&variant.ident);
+	}
+
+	let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+	let arms = variants.iter().map(|variant| quote! {
+		#name::#variant => #name::#variant as usize,
+	});
+	let tags = variants.iter().map(|variant| quote! {
+		#name::#variant as usize,
+	});
+	let values = variants.iter().map(|variant| quote! {
+		#name::#variant,
+	});
+	let lookups = variants.iter().map(|variant| quote! {
+		if tag == #name::#variant as usize {
+			return Some(#name::#variant);
+		}
+	});
+
+	Ok(quote! {
+		#(#checks)*
+
+		impl #impl_generics ::fsm::EnumTag for #name #ty_generics #where_clause {
+			fn tag_number(&self) -> usize {
+				match *self {
+					#(#arms)*
+				}
+			}
+
+			fn max_tag_number() -> usize {
+				let tags = [#(#tags)*];
+				tags.iter().cloned().max().unwrap_or(0)
+			}
+
+			fn from_tag_number(tag: usize) -> Option<Self> {
+				#(#lookups)*
+				None
+			}
+
+			fn variants() -> impl Iterator<Item = Self> {
+				::core::iter::IntoIterator::into_iter([#(#values)*])
+			}
+		}
+	})
+}
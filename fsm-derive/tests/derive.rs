@@ -0,0 +1,73 @@
+extern crate fsm_derive;
+extern crate fsm;
+
+use fsm::{EnumTag, Machine};
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, fsm_derive::EnumTag)]
+enum TurnStyleState {
+	Locked,
+	Unlocked,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, fsm_derive::EnumTag)]
+enum TurnStyleEvent {
+	Push,
+	InsertCoin,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, fsm_derive::EnumTag)]
+enum Sparse {
+	First = 3,
+	Second,
+	Largest = 12,
+	Last = 7,
+}
+
+#[test]
+fn test_sequential_tags() {
+	assert_eq!(TurnStyleState::Locked.tag_number(), 0);
+	assert_eq!(TurnStyleState::Unlocked.tag_number(), 1);
+	assert_eq!(TurnStyleState::max_tag_number(), 1);
+	assert_eq!(TurnStyleEvent::max_tag_number(), 1);
+}
+
+#[test]
+fn test_explicit_discriminants() {
+	assert_eq!(Sparse::First.tag_number(), 3);
+	assert_eq!(Sparse::Second.tag_number(), 4);
+	assert_eq!(Sparse::Largest.tag_number(), 12);
+	assert_eq!(Sparse::Last.tag_number(), 7);
+	assert_eq!(Sparse::max_tag_number(), 12);
+}
+
+#[test]
+fn test_from_tag_number() {
+	assert_eq!(TurnStyleEvent::from_tag_number(1), Some(TurnStyleEvent::InsertCoin));
+	assert_eq!(TurnStyleEvent::from_tag_number(2), None);
+	assert_eq!(Sparse::from_tag_number(4), Some(Sparse::Second));
+	assert_eq!(Sparse::from_tag_number(7), Some(Sparse::Last));
+	assert_eq!(Sparse::from_tag_number(5), None);
+}
+
+#[test]
+fn test_variants() {
+	assert_eq!(TurnStyleState::variants().collect::<Vec<_>>(), [TurnStyleState::Locked, TurnStyleState::Unlocked]);
+	assert_eq!(Sparse::variants().collect::<Vec<_>>(), [Sparse::First, Sparse::Second, Sparse::Largest, Sparse::Last]);
+}
+
+#[test]
+fn test_derived_machine() {
+	let mut machine = Machine::new(TurnStyleState::Locked);
+	machine.add_transition(
+		TurnStyleState::Locked, TurnStyleEvent::InsertCoin,
+		TurnStyleState::Unlocked, |_,_| {}
+	);
+	machine.add_transition(
+		TurnStyleState::Unlocked, TurnStyleEvent::Push,
+		TurnStyleState::Locked, |_,_| {}
+	);
+	machine.on_event(TurnStyleEvent::InsertCoin);
+	assert_eq!(machine.current_state(), TurnStyleState::Unlocked);
+	machine.on_event(TurnStyleEvent::Push);
+	assert_eq!(machine.current_state(), TurnStyleState::Locked);
+}
@@ -0,0 +1,338 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use futures::future::{BoxFuture, LocalBoxFuture};
+use futures::stream::{Stream, StreamExt};
+
+use super::chart::Chart;
+use super::run::{kind_of, Call, Drive, Goal, Run};
+use super::{
+	machine_constructors, Clock, Closures, DenseStorage, EnumTag, EventKind, StateMachine, Storage,
+	SystemClock, TransitionOutcome,
+};
+
+/// Async actions are boxed functions returning a future that the machine awaits before moving on,
+/// they take an argument of the event that triggered them along with mutable access to the
+/// machine's context
+pub type AsyncAction<'a, S, E, C = ()> = Box<dyn for<'b> FnMut(&'b S, &'b E, &'b mut C) -> LocalBoxFuture<'b, ()> + 'a>;
+
+/// Async predicates are used to filter down whether a transition can occur, resolving to true if
+/// it can
+pub type AsyncPredicate<'a, S, E, C = ()> = Box<dyn for<'b> Fn(&'b S, &'b E, &'b C) -> LocalBoxFuture<'b, bool> + 'a>;
+
+/// Async state actions are run when a state is entered or exited, or its timed transition is taken
+pub type AsyncStateAction<'a, S, C = ()> = Box<dyn for<'b> FnMut(&'b S, &'b mut C) -> LocalBoxFuture<'b, ()> + 'a>;
+
+/// Send async actions are async actions that, along with the futures they return, can be sent to
+/// and shared between threads
+pub type SendAsyncAction<'a, S, E, C = ()> = Box<dyn for<'b> FnMut(&'b S, &'b E, &'b mut C) -> BoxFuture<'b, ()> + Send + Sync + 'a>;
+
+/// Send async predicates are async predicates that can be sent to and shared between threads
+pub type SendAsyncPredicate<'a, S, E, C = ()> = Box<dyn for<'b> Fn(&'b S, &'b E, &'b C) -> BoxFuture<'b, bool> + Send + Sync + 'a>;
+
+/// Send async state actions are async state actions that can be sent to and shared between threads
+pub type SendAsyncStateAction<'a, S, C = ()> = Box<dyn for<'b> FnMut(&'b S, &'b mut C) -> BoxFuture<'b, ()> + Send + Sync + 'a>;
+
+/// The LocalAsync closures return futures that need not be `Send`, they are those of an
+/// AsyncMachine
+pub enum LocalAsync {}
+
+impl<'a, S, E, C> Closures<'a, S, E, C> for LocalAsync {
+	type Action = AsyncAction<'a, S, E, C>;
+	type Predicate = AsyncPredicate<'a, S, E, C>;
+	type StateAction = AsyncStateAction<'a, S, C>;
+	type Clock = dyn Clock + 'a;
+}
+
+/// The SendAsync closures, the futures they return and the clock timing them are `Send`, they
+/// are those of a SendAsyncMachine
+pub enum SendAsync {}
+
+impl<'a, S, E, C> Closures<'a, S, E, C> for SendAsync {
+	type Action = SendAsyncAction<'a, S, E, C>;
+	type Predicate = SendAsyncPredicate<'a, S, E, C>;
+	type StateAction = SendAsyncStateAction<'a, S, C>;
+	type Clock = dyn Clock + Send + Sync + 'a;
+}
+
+/// The AsyncMachine is a Machine whose predicates and actions are async, so they can wait on I/O
+/// without blocking the executor. Events are handled one at a time, each one's predicates and
+/// actions being awaited in turn before the next event is taken. Its closures and futures need
+/// not be Send, so they can hold on to local state, use a SendAsyncMachine to run on
+/// multi-threaded executors
+pub type AsyncMachine<'a, S, E, C = (), B = DenseStorage> = StateMachine<'a, S, E, C, B, LocalAsync>;
+
+/// The SendAsyncMachine is an AsyncMachine whose predicates, actions and clock are Send and Sync,
+/// so the futures it returns are Send whenever its states, events and context are
+pub type SendAsyncMachine<'a, S, E, C = (), B = DenseStorage> = StateMachine<'a, S, E, C, B, SendAsync>;
+
+/// Implements the constructors, registration and drivers of an async StateMachine whose closures
+/// are boxed by the given Closures, returning the given boxed futures and having the given bounds
+macro_rules! async_closures {
+	($closures:ty, $future:ident $(, $bound:path)*) => {
+		machine_constructors!($closures);
+
+		impl<'a, S: EnumTag, E: EventKind, C, B: Storage> StateMachine<'a, S, E, C, B, $closures> {
+			/// Registers a new valid transition with the FSM whose action can modify the machine's
+			/// context, returns false if the state already has an unconditional transition for this
+			/// event or the event's tag is beyond its `max_tag_number`
+			pub fn add_transition<F>(&mut self, in_state: S, on_event: E::Kind, next_state: S, action: F) -> bool
+			where F: for<'b> FnMut(&'b S, &'b E, &'b mut C) -> $future<'b, ()> $(+ $bound)* + 'a {
+				self.chart.add_transition(in_state, on_event, next_state, None, Box::new(action))
+			}
+
+			/// Registers a new transition with the FSM that only occurs if the predicate resolves to
+			/// true, as `Machine::add_guarded_transition`
+			pub fn add_guarded_transition<P, F>(&mut self, in_state: S, on_event: E::Kind, next_state: S, predicate: P, action: F) -> bool
+			where P: for<'b> Fn(&'b S, &'b E, &'b C) -> $future<'b, bool> $(+ $bound)* + 'a,
+			      F: for<'b> FnMut(&'b S, &'b E, &'b mut C) -> $future<'b, ()> $(+ $bound)* + 'a {
+				self.chart.add_transition(in_state, on_event, next_state, Some(Box::new(predicate)), Box::new(action))
+			}
+
+			/// Sets the action performed whenever the machine enters the given state, replacing any
+			/// previous entry action for that state
+			pub fn set_on_enter<F>(&mut self, state: S, action: F)
+			where F: for<'b> FnMut(&'b S, &'b mut C) -> $future<'b, ()> $(+ $bound)* + 'a {
+				self.chart.record_mut(state).on_enter = Some(Box::new(action));
+			}
+
+			/// Sets the action performed whenever the machine exits the given state, replacing any
+			/// previous exit action for that state
+			pub fn set_on_exit<F>(&mut self, state: S, action: F)
+			where F: for<'b> FnMut(&'b S, &'b mut C) -> $future<'b, ()> $(+ $bound)* + 'a {
+				self.chart.record_mut(state).on_exit = Some(Box::new(action));
+			}
+
+			/// Registers a transition that the machine takes once it has been in the given state for
+			/// the given time, as `Machine::add_timed_transition`
+			pub fn add_timed_transition<F>(&mut self, in_state: S, after: Duration, next_state: S, action: F) -> bool
+			where F: for<'b> FnMut(&'b S, &'b mut C) -> $future<'b, ()> $(+ $bound)* + 'a {
+				self.clock.is_some() && self.chart.add_timed_transition(in_state, after, next_state, Box::new(action))
+			}
+
+			/// Replaces the clock used to time how long the machine has been in each state, as
+			/// `Machine::set_clock`
+			pub fn set_clock<T>(&mut self, clock: T)
+			where T: Clock $(+ $bound)* + 'a {
+				self.run.set_clock(clock.now());
+				self.clock = Some(Box::new(clock));
+			}
+
+			/// Enters the initial substates of the state the machine was constructed in, as
+			/// `Machine::start`, awaiting their entry actions
+			pub async fn start(&mut self) {
+				self.drive(Drive::new(Goal::Start, None)).await;
+			}
+
+			/// Tick the State Machine with an Event, resolving to whether it triggered a transition.
+			/// The transition is chosen, run and followed by any deferred and posted events as for
+			/// `Machine::on_event`, each predicate and action being awaited before the next starts
+			pub async fn on_event(&mut self, event_type: E) -> TransitionOutcome<S> {
+				self.drive(Drive::new(Goal::Event, Some(event_type))).await.outcome()
+			}
+
+			/// Processes events waiting in the event queue, up to the run limit, resolving to how many
+			/// were processed
+			pub async fn process_pending(&mut self) -> usize {
+				self.drive(Drive::new(Goal::Pending, None)).await.processed()
+			}
+
+			/// Takes every timed transition that is due at the given time, as `Machine::advance`
+			pub async fn advance(&mut self, now: Duration) -> Vec<TransitionOutcome<S>> {
+				self.drive(Drive::new(Goal::Advance(now), None)).await.outcomes()
+			}
+
+			/// Ticks the State Machine with every event from the stream in turn, resolving once the
+			/// stream has ended
+			pub async fn run<St>(&mut self, events: St)
+			where St: Stream<Item = E> {
+				futures::pin_mut!(events);
+				while let Some(event) = events.next().await {
+					self.on_event(event).await;
+				}
+			}
+
+			/// Awaits each Call of the Drive until it is done
+			async fn drive(&mut self, mut drive: Drive<S, E>) -> Drive<S, E> {
+				while let Some(call) = drive.next(&self.chart, &mut self.run, self.clock.as_deref(), kind_of) {
+					let context = &mut self.run.context;
+					match call {
+						Call::Check(index) => {
+							let passed = match self.chart.actions[index].predicate {
+								Some(ref predicate) => predicate(&self.run.state, drive.event(), context).await,
+								None => true,
+							};
+							drive.passed(passed);
+						},
+						Call::Exit(state) => if let Some(on_exit) = self.chart.on_exit_mut(state) {
+							on_exit(&state, context).await;
+						},
+						Call::Act(index, previous) => (self.chart.actions[index].action)(&previous, drive.event(), context).await,
+						Call::TimeOut(timed, previous) => if let Some(action) = self.chart.timeout_action_mut(timed) {
+							action(&previous, context).await;
+						},
+						Call::Enter(state) => if let Some(on_enter) = self.chart.on_enter_mut(state) {
+							on_enter(&state, context).await;
+						},
+					}
+				}
+				drive
+			}
+		}
+	};
+}
+
+async_closures!(LocalAsync, LocalBoxFuture);
+async_closures!(SendAsync, BoxFuture, Send, Sync);
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::ManualClock;
+	use alloc::rc::Rc;
+	use core::cell::RefCell;
+	use futures::executor::block_on;
+	use futures::stream;
+
+	#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+	enum DoorState {
+		Closed,
+		Opening,
+		Open,
+	}
+
+	#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+	enum DoorEvent {
+		Badge,
+		Opened,
+		Close,
+	}
+
+	impl EnumTag for DoorState {
+		fn tag_number(&self) -> usize {
+			*self as usize
+		}
+		fn max_tag_number() -> usize {
+			DoorState::Open as usize
+		}
+	}
+
+	impl EnumTag for DoorEvent {
+		fn tag_number(&self) -> usize {
+			*self as usize
+		}
+		fn max_tag_number() -> usize {
+			DoorEvent::Close as usize
+		}
+	}
+
+	struct Door {
+		authorised: bool,
+		log: Vec<&'static str>,
+	}
+
+	fn door<'a>(authorised: bool) -> AsyncMachine<'a, DoorState, DoorEvent, Door> {
+		let mut machine = AsyncMachine::with_context(DoorState::Closed, Door {
+			authorised,
+			log: Vec::new(),
+		});
+		machine.add_guarded_transition(
+			DoorState::Closed, DoorEvent::Badge, DoorState::Opening,
+			|_, _, door| Box::pin(async move { door.authorised }),
+			|_, _, door| Box::pin(async move { door.log.push("unlock") })
+		);
+		machine.add_transition(
+			DoorState::Opening, DoorEvent::Opened, DoorState::Open,
+			|_, _, door| Box::pin(async move { door.log.push("opened") })
+		);
+		machine.add_transition(
+			DoorState::Open, DoorEvent::Close, DoorState::Closed,
+			|_, _, door| Box::pin(async move { door.log.push("lock") })
+		);
+		machine.set_on_exit(DoorState::Closed, |_, door| Box::pin(async move { door.log.push("exit closed") }));
+		machine.set_on_enter(DoorState::Closed, |_, door| Box::pin(async move { door.log.push("enter closed") }));
+		machine
+	}
+
+	#[test]
+	fn test_async_machine() {
+		let mut machine = door(true);
+		block_on(async {
+			assert_eq!(machine.on_event(DoorEvent::Opened).await, TransitionOutcome::Unhandled { state: DoorState::Closed });
+			assert_eq!(machine.on_event(DoorEvent::Badge).await, TransitionOutcome::Handled {
+				previous: DoorState::Closed,
+				current: DoorState::Opening,
+			});
+		});
+		assert_eq!(machine.context().log, ["exit closed", "unlock"]);
+
+		let mut machine = door(false);
+		assert!(!block_on(machine.on_event(DoorEvent::Badge)).is_handled());
+		machine.context_mut().authorised = true;
+		assert!(block_on(machine.on_event(DoorEvent::Badge)).is_handled());
+	}
+
+	#[test]
+	fn test_local_closures() {
+		let log = Rc::new(RefCell::new(Vec::new()));
+		let mut machine = AsyncMachine::new(DoorState::Closed);
+		let opened = log.clone();
+		machine.add_transition(DoorState::Closed, DoorEvent::Badge, DoorState::Open, move |_, _, _| {
+			let opened = opened.clone();
+			Box::pin(async move { opened.borrow_mut().push("opened") })
+		});
+		assert!(block_on(machine.on_event(DoorEvent::Badge)).is_handled());
+		assert_eq!(*log.borrow(), ["opened"]);
+	}
+
+	#[test]
+	fn test_futures_are_send() {
+		fn assert_send<T: Send>(_: &T) {}
+
+		let mut machine = SendAsyncMachine::with_context(DoorState::Closed, Door {
+			authorised: true,
+			log: Vec::new(),
+		});
+		machine.add_guarded_transition(
+			DoorState::Closed, DoorEvent::Badge, DoorState::Open,
+			|_, _, door| Box::pin(async move { door.authorised }),
+			|_, _, door| Box::pin(async move { door.log.push("unlock") })
+		);
+		assert_send(&machine.on_event(DoorEvent::Badge));
+		assert_send(&machine.run(stream::iter(vec![DoorEvent::Close])));
+	}
+
+	#[test]
+	fn test_timed_transition_and_posting() {
+		let clock = ManualClock::new();
+		let mut machine = door(true);
+		machine.set_clock(clock.clone());
+		assert!(machine.add_timed_transition(
+			DoorState::Open, Duration::from_secs(5), DoorState::Closed,
+			|_, door| Box::pin(async move { door.log.push("timed out") })
+		));
+		let queue = machine.event_queue();
+		block_on(async {
+			machine.on_event(DoorEvent::Badge).await;
+			queue.post(DoorEvent::Opened);
+			assert_eq!(machine.process_pending().await, 1);
+			assert_eq!(machine.current_state(), DoorState::Open);
+			assert!(machine.advance(clock.now()).await.is_empty());
+			clock.advance(Duration::from_secs(5));
+			assert_eq!(machine.advance(clock.now()).await, [TransitionOutcome::Handled {
+				previous: DoorState::Open,
+				current: DoorState::Closed,
+			}]);
+		});
+		assert_eq!(machine.context().log, ["exit closed", "unlock", "opened", "timed out", "enter closed"]);
+	}
+
+	#[test]
+	fn test_run_stream() {
+		let mut machine = door(true);
+		block_on(machine.run(stream::iter(vec![DoorEvent::Badge, DoorEvent::Opened, DoorEvent::Close])));
+		assert_eq!(machine.current_state(), DoorState::Closed);
+		assert_eq!(machine.context().log, ["exit closed", "unlock", "opened", "lock", "enter closed"]);
+	}
+}
@@ -0,0 +1,449 @@
+use core::error::Error;
+use core::fmt;
+use core::time::Duration;
+
+use alloc::vec::Vec;
+
+use super::{Clock, Closures, DenseStorage, EnumTag, EventKind, History, Local, Sendable, StateMachine, Storage};
+
+/// The BuildError explains why a MachineBuilder could not build its Machine, naming one
+/// registration that failed
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum BuildError<S, K> {
+	/// The state's tag is greater than its type's `max_tag_number`
+	StateOutOfRange(S),
+	/// The event's tag is greater than its type's `max_tag_number`
+	EventOutOfRange(K),
+	/// The state already has an unconditional transition for the event, so a new transition could
+	/// never be taken or would replace it
+	ConflictingTransition {
+		/// the state the transitions leave
+		state: S,
+		/// the kind of event that triggers them
+		event: K,
+	},
+	/// The state already has a timed transition
+	ConflictingTimedTransition(S),
+	/// The machine has no clock to time the state's timed transition, as without the `std` feature
+	/// a clock must be given before any timed transitions
+	NoClock(S),
+	/// The parent is the state itself or one of its substates
+	CyclicParent {
+		/// the state whose parent was being set
+		state: S,
+		/// the would-be parent
+		parent: S,
+	},
+	/// The substate's parent is not the state it was to be the initial substate of
+	NotASubstate {
+		/// the state whose initial substate was being set
+		parent: S,
+		/// the would-be initial substate
+		substate: S,
+	},
+}
+
+impl<S: fmt::Debug, K: fmt::Debug> fmt::Display for BuildError<S, K> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			BuildError::StateOutOfRange(ref state) =>
+				write!(f, "state {:?} has a tag beyond its type's max_tag_number", state),
+			BuildError::EventOutOfRange(ref event) =>
+				write!(f, "event {:?} has a tag beyond its type's max_tag_number", event),
+			BuildError::ConflictingTransition { ref state, ref event } =>
+				write!(f, "state {:?} already has an unconditional transition for event {:?}", state, event),
+			BuildError::ConflictingTimedTransition(ref state) =>
+				write!(f, "state {:?} already has a timed transition", state),
+			BuildError::NoClock(ref state) =>
+				write!(f, "state {:?} cannot have a timed transition, as the machine has no clock", state),
+			BuildError::CyclicParent { ref state, ref parent } =>
+				write!(f, "state {:?} cannot have {:?} as its parent, as it is the state or one of its substates", state, parent),
+			BuildError::NotASubstate { ref parent, ref substate } =>
+				write!(f, "state {:?} cannot be the initial substate of {:?}, as it is not one of its substates", substate, parent),
+		}
+	}
+}
+
+impl<S: fmt::Debug, K: fmt::Debug> Error for BuildError<S, K> {}
+
+/// The result of building a StateMachine, every registration that failed if it could not be built
+type BuildResult<'a, S, E, C, B, K> = Result<StateMachine<'a, S, E, C, B, K>, Vec<BuildError<S, <E as EventKind>::Kind>>>;
+
+/// The StateMachineBuilder registers a StateMachine's transitions and state behaviour fluently,
+/// checking each registration so that `build` can report every mistake rather than them being
+/// ignored or panicking. Like the StateMachine it builds, it is usually named through
+/// MachineBuilder or SyncMachineBuilder; the async machines have no builder
+pub struct StateMachineBuilder<'a, S: EnumTag, E: EventKind, C = (), B: Storage = DenseStorage, K: Closures<'a, S, E, C> = Local> {
+	/// the machine being built, or None if its initial state is out of range
+	machine: Option<StateMachine<'a, S, E, C, B, K>>,
+	errors: Vec<BuildError<S, E::Kind>>,
+}
+
+/// The MachineBuilder builds a Machine
+pub type MachineBuilder<'a, S, E, C = (), B = DenseStorage> = StateMachineBuilder<'a, S, E, C, B, Local>;
+
+/// The SyncMachineBuilder builds a SyncMachine
+pub type SyncMachineBuilder<'a, S, E, C = (), B = DenseStorage> = StateMachineBuilder<'a, S, E, C, B, Sendable>;
+
+/// Implements the constructors and registration of a StateMachineBuilder whose closures are boxed
+/// by the given Closures, each closure having the given bounds
+macro_rules! builder_closures {
+	($closures:ty $(, $bound:path)*) => {
+		impl<'a, S: EnumTag, E: EventKind> StateMachineBuilder<'a, S, E, (), DenseStorage, $closures> {
+			/// Starts building a machine with the given initial state
+			pub fn new(initial_state: S) -> StateMachineBuilder<'a, S, E, (), DenseStorage, $closures> {
+				Self::with_context(initial_state, ())
+			}
+		}
+
+		impl<'a, S: EnumTag, E: EventKind, C> StateMachineBuilder<'a, S, E, C, DenseStorage, $closures> {
+			/// Starts building a machine with the given initial state, owning the given context
+			pub fn with_context(initial_state: S, context: C) -> StateMachineBuilder<'a, S, E, C, DenseStorage, $closures> {
+				Self::with_storage(initial_state, context)
+			}
+		}
+
+		impl<'a, S: EnumTag, E: EventKind, C, B: Storage> StateMachineBuilder<'a, S, E, C, B, $closures> {
+			/// Starts building a machine with the given initial state, owning the given context,
+			/// whose transitions are held by the storage backend `B`
+			pub fn with_storage(initial_state: S, context: C) -> StateMachineBuilder<'a, S, E, C, B, $closures> {
+				match check_state(initial_state) {
+					Ok(()) => StateMachineBuilder {
+						machine: Some(StateMachine::<S, E, C, B, $closures>::with_storage(initial_state, context)),
+						errors: Vec::new(),
+					},
+					Err(error) => StateMachineBuilder {
+						machine: None,
+						errors: Vec::from([error]),
+					},
+				}
+			}
+
+			/// Adds a transition, as `Machine::add_transition`
+			pub fn transition<F>(self, in_state: S, on_event: E::Kind, next_state: S, action: F) -> Self
+			where F: FnMut(&S, &E) $(+ $bound)* + 'a {
+				self.register(&[in_state, next_state], &[on_event], |machine| {
+					check_conflict(machine.add_transition(in_state, on_event, next_state, action), in_state, on_event)
+				})
+			}
+
+			/// Adds a transition, as `Machine::add_transition_with_context`
+			pub fn transition_with_context<F>(self, in_state: S, on_event: E::Kind, next_state: S, action: F) -> Self
+			where F: FnMut(&S, &E, &mut C) $(+ $bound)* + 'a {
+				self.register(&[in_state, next_state], &[on_event], |machine| {
+					check_conflict(machine.add_transition_with_context(in_state, on_event, next_state, action), in_state, on_event)
+				})
+			}
+
+			/// Adds a guarded transition, as `Machine::add_guarded_transition`
+			pub fn guarded_transition<P, F>(self, in_state: S, on_event: E::Kind, next_state: S, predicate: P, action: F) -> Self
+			where P: Fn(&S, &E) -> bool $(+ $bound)* + 'a, F: FnMut(&S, &E) $(+ $bound)* + 'a {
+				self.register(&[in_state, next_state], &[on_event], |machine| {
+					check_conflict(machine.add_guarded_transition(in_state, on_event, next_state, predicate, action), in_state, on_event)
+				})
+			}
+
+			/// Adds a guarded transition, as `Machine::add_guarded_transition_with_context`
+			pub fn guarded_transition_with_context<P, F>(self, in_state: S, on_event: E::Kind, next_state: S, predicate: P, action: F) -> Self
+			where P: Fn(&S, &E, &C) -> bool $(+ $bound)* + 'a, F: FnMut(&S, &E, &mut C) $(+ $bound)* + 'a {
+				self.register(&[in_state, next_state], &[on_event], |machine| {
+					check_conflict(machine.add_guarded_transition_with_context(in_state, on_event, next_state, predicate, action), in_state, on_event)
+				})
+			}
+
+			/// Adds a timed transition, as `Machine::add_timed_transition`
+			pub fn timed_transition<F>(self, in_state: S, after: Duration, next_state: S, action: F) -> Self
+			where F: FnMut(&S) $(+ $bound)* + 'a {
+				self.register(&[in_state, next_state], &[], |machine| {
+					let added = machine.add_timed_transition(in_state, after, next_state, action);
+					check_timed(machine, added, in_state)
+				})
+			}
+
+			/// Adds a timed transition, as `Machine::add_timed_transition_with_context`
+			pub fn timed_transition_with_context<F>(self, in_state: S, after: Duration, next_state: S, action: F) -> Self
+			where F: FnMut(&S, &mut C) $(+ $bound)* + 'a {
+				self.register(&[in_state, next_state], &[], |machine| {
+					let added = machine.add_timed_transition_with_context(in_state, after, next_state, action);
+					check_timed(machine, added, in_state)
+				})
+			}
+
+			/// Sets a state's entry action, as `Machine::set_on_enter`
+			pub fn on_enter<F>(self, state: S, action: F) -> Self
+			where F: FnMut(&S) $(+ $bound)* + 'a {
+				self.register(&[state], &[], |machine| {
+					machine.set_on_enter(state, action);
+					Ok(())
+				})
+			}
+
+			/// Sets a state's entry action, as `Machine::set_on_enter_with_context`
+			pub fn on_enter_with_context<F>(self, state: S, action: F) -> Self
+			where F: FnMut(&S, &mut C) $(+ $bound)* + 'a {
+				self.register(&[state], &[], |machine| {
+					machine.set_on_enter_with_context(state, action);
+					Ok(())
+				})
+			}
+
+			/// Sets a state's exit action, as `Machine::set_on_exit`
+			pub fn on_exit<F>(self, state: S, action: F) -> Self
+			where F: FnMut(&S) $(+ $bound)* + 'a {
+				self.register(&[state], &[], |machine| {
+					machine.set_on_exit(state, action);
+					Ok(())
+				})
+			}
+
+			/// Sets a state's exit action, as `Machine::set_on_exit_with_context`
+			pub fn on_exit_with_context<F>(self, state: S, action: F) -> Self
+			where F: FnMut(&S, &mut C) $(+ $bound)* + 'a {
+				self.register(&[state], &[], |machine| {
+					machine.set_on_exit_with_context(state, action);
+					Ok(())
+				})
+			}
+
+			/// Sets the machine's clock, as `Machine::set_clock`
+			pub fn clock<T>(self, clock: T) -> Self
+			where T: Clock $(+ $bound)* + 'a {
+				self.register(&[], &[], |machine| {
+					machine.set_clock(clock);
+					Ok(())
+				})
+			}
+		}
+	};
+}
+
+builder_closures!(Local);
+builder_closures!(Sendable, Send, Sync);
+
+impl<'a, S: EnumTag, E: EventKind, C, B: Storage, K: Closures<'a, S, E, C>> StateMachineBuilder<'a, S, E, C, B, K> {
+	/// Makes a state a substate of the given parent, as `Machine::set_parent`
+	pub fn parent(self, state: S, parent: S) -> Self {
+		self.register(&[state, parent], &[], |machine| {
+			if machine.set_parent(state, parent) {
+				Ok(())
+			} else {
+				Err(BuildError::CyclicParent { state, parent })
+			}
+		})
+	}
+
+	/// Sets a parent's initial substate, as `Machine::set_initial_substate`
+	pub fn initial_substate(self, parent: S, substate: S) -> Self {
+		self.register(&[parent, substate], &[], |machine| {
+			if machine.set_initial_substate(parent, substate) {
+				Ok(())
+			} else {
+				Err(BuildError::NotASubstate { parent, substate })
+			}
+		})
+	}
+
+	/// Sets a state's history, as `Machine::set_history`
+	pub fn history(self, state: S, history: History) -> Self {
+		self.register(&[state], &[], |machine| {
+			machine.set_history(state, history);
+			Ok(())
+		})
+	}
+
+	/// Defers events of the given kind in a state, as `Machine::defer_event`
+	pub fn defer_event(self, state: S, on_event: E::Kind) -> Self {
+		self.register(&[state], &[on_event], |machine| {
+			machine.defer_event(state, on_event);
+			Ok(())
+		})
+	}
+
+	/// Marks a state as final, as `Machine::mark_final`
+	pub fn mark_final(self, state: S) -> Self {
+		self.register(&[state], &[], |machine| {
+			machine.mark_final(state);
+			Ok(())
+		})
+	}
+
+	/// Sets the machine's run limit, as `Machine::set_run_limit`
+	pub fn run_limit(self, run_limit: usize) -> Self {
+		self.register(&[], &[], |machine| {
+			machine.set_run_limit(run_limit);
+			Ok(())
+		})
+	}
+
+	/// Finishes building the machine, or reports every registration that failed in the order
+	/// they were made
+	pub fn build(self) -> BuildResult<'a, S, E, C, B, K> {
+		match self.machine {
+			Some(machine) if self.errors.is_empty() => Ok(machine),
+			_ => Err(self.errors),
+		}
+	}
+
+	/// Checks the given states and events are in range then registers something with the machine,
+	/// recording why if it fails. Registrations with a state or event out of range are skipped
+	fn register<F>(mut self, states: &[S], events: &[E::Kind], register: F) -> Self
+	where F: FnOnce(&mut StateMachine<'a, S, E, C, B, K>) -> Result<(), BuildError<S, E::Kind>> {
+		let errors = self.errors.len();
+		self.errors.extend(states.iter().filter_map(|&state| check_state(state).err()));
+		self.errors.extend(events.iter().filter(|event| event.tag_number() > E::Kind::max_tag_number()).map(|&event| BuildError::EventOutOfRange(event)));
+		if let Some(machine) = self.machine.as_mut().filter(|_| self.errors.len() == errors) {
+			if let Err(error) = register(machine) {
+				self.errors.push(error);
+			}
+		}
+		self
+	}
+}
+
+fn check_state<S: EnumTag, K>(state: S) -> Result<(), BuildError<S, K>> {
+	if state.tag_number() > S::max_tag_number() {
+		Err(BuildError::StateOutOfRange(state))
+	} else {
+		Ok(())
+	}
+}
+
+fn check_conflict<S, K>(added: bool, state: S, event: K) -> Result<(), BuildError<S, K>> {
+	if added {
+		Ok(())
+	} else {
+		Err(BuildError::ConflictingTransition { state, event })
+	}
+}
+
+fn check_timed<'a, S: EnumTag, E: EventKind, C, B: Storage, K: Closures<'a, S, E, C>>(machine: &StateMachine<'a, S, E, C, B, K>, added: bool, state: S) -> Result<(), BuildError<S, E::Kind>> {
+	if added {
+		Ok(())
+	} else if machine.clock.is_none() {
+		Err(BuildError::NoClock(state))
+	} else {
+		Err(BuildError::ConflictingTimedTransition(state))
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::time::Duration;
+
+	use super::*;
+	use super::super::{ManualClock, SortedStorage};
+
+	#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+	enum DoorState {
+		Closed,
+		Open,
+		Locked,
+		/// a state whose tag is beyond what `max_tag_number` admits
+		Ajar,
+	}
+
+	#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+	enum DoorEvent {
+		Open,
+		Close,
+		Lock,
+		Unlock,
+	}
+
+	impl EnumTag for DoorState {
+		fn tag_number(&self) -> usize {
+			*self as usize
+		}
+		fn max_tag_number() -> usize {
+			DoorState::Locked as usize
+		}
+	}
+
+	impl EnumTag for DoorEvent {
+		fn tag_number(&self) -> usize {
+			*self as usize
+		}
+		fn max_tag_number() -> usize {
+			DoorEvent::Lock as usize
+		}
+	}
+
+	#[test]
+	fn test_build() {
+		let mut machine = MachineBuilder::new(DoorState::Closed)
+			.transition(DoorState::Closed, DoorEvent::Open, DoorState::Open, |_,_| {})
+			.transition(DoorState::Open, DoorEvent::Close, DoorState::Closed, |_,_| {})
+			.guarded_transition(DoorState::Closed, DoorEvent::Lock, DoorState::Locked, |_,_| true, |_,_| {})
+			.clock(ManualClock::new())
+			.timed_transition(DoorState::Open, Duration::from_secs(30), DoorState::Closed, |_| {})
+			.mark_final(DoorState::Locked)
+			.build()
+			.unwrap();
+		assert!(machine.on_event(DoorEvent::Open).is_handled());
+		assert!(machine.on_event(DoorEvent::Close).is_handled());
+		assert!(machine.on_event(DoorEvent::Lock).is_handled());
+		assert_eq!(machine.current_state(), DoorState::Locked);
+		assert!(machine.is_final(DoorState::Locked));
+	}
+
+	#[test]
+	fn test_build_sync_machine() {
+		let mut machine = SyncMachineBuilder::<_, DoorEvent, u32, SortedStorage>::with_storage(DoorState::Closed, 0)
+			.transition_with_context(DoorState::Closed, DoorEvent::Open, DoorState::Open, |_,_,opened| *opened += 1)
+			.transition(DoorState::Open, DoorEvent::Close, DoorState::Closed, |_,_| {})
+			.build()
+			.unwrap();
+		assert!(machine.on_event(DoorEvent::Open).is_handled());
+		assert!(machine.on_event(DoorEvent::Close).is_handled());
+		assert_eq!(*machine.context(), 1);
+	}
+
+	#[test]
+	fn test_build_errors() {
+		let conflict = MachineBuilder::<_, DoorEvent>::new(DoorState::Closed)
+			.transition(DoorState::Closed, DoorEvent::Open, DoorState::Open, |_,_| {})
+			.transition(DoorState::Closed, DoorEvent::Open, DoorState::Locked, |_,_| {})
+			.transition(DoorState::Open, DoorEvent::Open, DoorState::Ajar, |_,_| {})
+			.build();
+		assert_eq!(conflict.err(), Some(vec![
+			BuildError::ConflictingTransition { state: DoorState::Closed, event: DoorEvent::Open },
+			BuildError::StateOutOfRange(DoorState::Ajar),
+		]));
+
+		let state = MachineBuilder::<_, DoorEvent>::new(DoorState::Closed)
+			.transition(DoorState::Open, DoorEvent::Close, DoorState::Ajar, |_,_| {})
+			.build();
+		assert_eq!(state.err(), Some(vec![BuildError::StateOutOfRange(DoorState::Ajar)]));
+
+		let event = MachineBuilder::<_, DoorEvent>::new(DoorState::Closed)
+			.transition(DoorState::Locked, DoorEvent::Unlock, DoorState::Closed, |_,_| {})
+			.build();
+		assert_eq!(event.err(), Some(vec![BuildError::EventOutOfRange(DoorEvent::Unlock)]));
+
+		let initial = MachineBuilder::<_, DoorEvent>::new(DoorState::Ajar)
+			.transition(DoorState::Closed, DoorEvent::Unlock, DoorState::Open, |_,_| {})
+			.build();
+		assert_eq!(initial.err(), Some(vec![
+			BuildError::StateOutOfRange(DoorState::Ajar),
+			BuildError::EventOutOfRange(DoorEvent::Unlock),
+		]));
+
+		let cycle = MachineBuilder::<_, DoorEvent>::new(DoorState::Closed)
+			.parent(DoorState::Locked, DoorState::Closed)
+			.parent(DoorState::Closed, DoorState::Locked)
+			.build();
+		assert_eq!(cycle.err(), Some(vec![BuildError::CyclicParent { state: DoorState::Closed, parent: DoorState::Locked }]));
+		assert_eq!(
+			BuildError::<DoorState, DoorEvent>::ConflictingTimedTransition(DoorState::Open).to_string(),
+			"state Open already has a timed transition"
+		);
+
+		#[cfg(not(feature = "std"))]
+		{
+			let unclocked = MachineBuilder::<_, DoorEvent>::new(DoorState::Closed)
+				.timed_transition(DoorState::Open, Duration::from_secs(30), DoorState::Closed, |_| {})
+				.build();
+			assert_eq!(unclocked.err(), Some(vec![BuildError::NoClock(DoorState::Open)]));
+		}
+	}
+}
@@ -0,0 +1,319 @@
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::iter;
+use core::time::Duration;
+
+use super::{EnumTag, History, Storage, TagMap, TagTable};
+
+/// The Transition records, for a given current state, what event type triggers it to move to
+/// what state, performing a specific action on the transition, filterable by a predicate function.
+/// Transitions for the same state and event form a chain, tried in the order they were added
+pub(crate) struct Transition<S, K, A, P> {
+	pub(crate) event: K,
+	pub(crate) next_state: S,
+	pub(crate) predicate: Option<P>,
+	pub(crate) action: A,
+	next: Option<usize>,
+}
+
+/// The Timeout records a transition a state takes once the machine has been in it for some time
+pub(crate) struct Timeout<S, X> {
+	pub(crate) after: Duration,
+	pub(crate) next_state: S,
+	pub(crate) action: X,
+}
+
+/// The StateTransitions records the actions to perform when entering and exiting a given state,
+/// which events it defers, its timeout and its place in the state hierarchy. Its Transitions are
+/// held by the Chart's table
+pub(crate) struct StateTransitions<S, X> {
+	pub(crate) state: Option<S>,
+	pub(crate) on_enter: Option<X>,
+	pub(crate) on_exit: Option<X>,
+	defers: Vec<usize>,
+	pub(crate) timeout: Option<Timeout<S, X>>,
+	pub(crate) parent: Option<S>,
+	pub(crate) initial: Option<S>,
+	pub(crate) history: History,
+	pub(crate) is_final: bool,
+}
+
+impl<S, X> StateTransitions<S, X> {
+	fn new() -> StateTransitions<S, X> {
+		StateTransitions {
+			state: None,
+			on_enter: None,
+			on_exit: None,
+			defers: Vec::new(),
+			timeout: None,
+			parent: None,
+			initial: None,
+			history: History::None,
+			is_final: false,
+		}
+	}
+}
+
+/// The Chart is what every machine is defined by: its initial state, its states and their
+/// hierarchy, and its transitions, whose predicates and actions are whichever boxed closures `P`,
+/// `A` and `X` the machine uses. It holds no running state, that is left to a Run.
+///
+/// The transitions are found through one table, keyed by state and event kind `K`, whose entries
+/// are the first of the Transitions for that state and event in a separate list. How that table
+/// and the records of each state are held is chosen by the Storage backend `B`
+pub(crate) struct Chart<S, K, B: Storage, A, P, X> {
+	pub(crate) initial_state: S,
+	pub(crate) transitions: B::Map<StateTransitions<S, X>>,
+	table: B::Table<usize>,
+	pub(crate) actions: Vec<Transition<S, K, A, P>>,
+	/// whether any state has a timed transition
+	pub(crate) timed: bool,
+}
+
+impl<S: EnumTag, K: EnumTag, B: Storage, A, P, X> Chart<S, K, B, A, P, X> {
+	pub(crate) fn new(initial_state: S) -> Chart<S, K, B, A, P, X> {
+		let mut chart = Chart {
+			initial_state,
+			transitions: B::Map::new(),
+			table: B::Table::new(K::max_tag_number()),
+			actions: Vec::new(),
+			timed: false,
+		};
+		chart.record_mut(initial_state);
+		chart
+	}
+
+	/// Registers a transition, returning false if the state already has an unconditional
+	/// transition for the event or the event's tag is beyond its `max_tag_number`
+	pub(crate) fn add_transition(&mut self, in_state: S, on_event: K, next_state: S, predicate: Option<P>, action: A) -> bool {
+		// an event beyond max_tag_number would land in the next state's row of the table
+		if on_event.tag_number() > K::max_tag_number() {
+			return false;
+		}
+		let (state, event) = (in_state.tag_number(), on_event.tag_number());
+		if self.candidates(state, event).any(|index| self.actions[index].predicate.is_none()) {
+			return false;
+		}
+		let index = self.actions.len();
+		match self.candidates(state, event).last() {
+			Some(last) => self.actions[last].next = Some(index),
+			None => if !self.table.insert(state, event, index) {
+				return false;
+			},
+		}
+		self.record_mut(next_state);
+		self.record_mut(in_state);
+		self.actions.push(Transition {
+			event: on_event,
+			next_state,
+			predicate,
+			action,
+			next: None,
+		});
+		true
+	}
+
+	/// Registers a timed transition, returning false if the state already has one
+	pub(crate) fn add_timed_transition(&mut self, in_state: S, after: Duration, next_state: S, action: X) -> bool {
+		self.record_mut(next_state);
+		let timeout = &mut self.record_mut(in_state).timeout;
+		if timeout.is_some() {
+			false
+		} else {
+			*timeout = Some(Timeout {
+				after,
+				next_state,
+				action,
+			});
+			self.timed = true;
+			true
+		}
+	}
+
+	/// Makes the state a substate of the parent, returning false if that would make a cycle
+	pub(crate) fn set_parent(&mut self, state: S, parent: S) -> bool {
+		if self.is_descendant_or_self(parent, state) {
+			false
+		} else {
+			self.record_mut(parent);
+			self.record_mut(state).parent = Some(parent);
+			true
+		}
+	}
+
+	/// Sets the parent's initial substate, returning false if it is not the parent's substate
+	pub(crate) fn set_initial_substate(&mut self, parent: S, substate: S) -> bool {
+		if self.parent(substate).map(|p| p.tag_number()) == Some(parent.tag_number()) {
+			self.record_mut(parent).initial = Some(substate);
+			true
+		} else {
+			false
+		}
+	}
+
+	pub(crate) fn defer_event(&mut self, state: S, on_event: K) {
+		let defers = &mut self.record_mut(state).defers;
+		if !defers.contains(&on_event.tag_number()) {
+			defers.push(on_event.tag_number());
+		}
+	}
+
+	pub(crate) fn is_final(&self, state: S) -> bool {
+		self.transitions.get(state.tag_number()).is_some_and(|r| r.is_final)
+	}
+
+	/// Retrieves the record of the given state for registering its behaviour, noting the state's
+	/// value so that it can be described later
+	pub(crate) fn record_mut(&mut self, state: S) -> &mut StateTransitions<S, X> {
+		let record = self.transitions.get_or_insert_with(state.tag_number(), StateTransitions::new);
+		record.state = Some(state);
+		record
+	}
+
+	pub(crate) fn record(&self, state: S) -> Option<&StateTransitions<S, X>> {
+		self.transitions.get(state.tag_number())
+	}
+
+	/// Retrieves the entry action of the state, if it has one
+	pub(crate) fn on_enter_mut(&mut self, state: S) -> Option<&mut X> {
+		self.transitions.get_mut(state.tag_number()).and_then(|r| r.on_enter.as_mut())
+	}
+
+	/// Retrieves the exit action of the state, if it has one
+	pub(crate) fn on_exit_mut(&mut self, state: S) -> Option<&mut X> {
+		self.transitions.get_mut(state.tag_number()).and_then(|r| r.on_exit.as_mut())
+	}
+
+	/// Retrieves the action of the timed state's timed transition, if it has one
+	pub(crate) fn timeout_action_mut(&mut self, timed: S) -> Option<&mut X> {
+		self.transitions.get_mut(timed.tag_number()).and_then(|r| r.timeout.as_mut()).map(|t| &mut t.action)
+	}
+
+	/// Iterates over the indices of the transitions for the state and event with the given tags,
+	/// in the order they are tried
+	fn candidates(&self, state: usize, event: usize) -> impl Iterator<Item = usize> + use<'_, S, K, B, A, P, X> {
+		self.chain(self.table.get(state, event).copied())
+	}
+
+	/// Iterates over the indices of the transitions in the chain starting at the given index
+	fn chain(&self, head: Option<usize>) -> impl Iterator<Item = usize> + use<'_, S, K, B, A, P, X> {
+		iter::successors(head, move |&index| self.actions[index].next)
+	}
+
+	/// Finds the transition offered an event of the given kind in the given state that is tried
+	/// after the one at the cursor, or the first if there is no cursor. The cursor holds the index
+	/// of a transition and the state, the given one or one of its ancestors, it belongs to
+	pub(crate) fn next_offered(&self, state: S, kind: usize, cursor: Option<(S, usize)>) -> Option<(S, usize)> {
+		let (mut owner, mut next) = match cursor {
+			Some((owner, index)) => (owner, self.actions[index].next),
+			None => (state, self.table.get(state.tag_number(), kind).copied()),
+		};
+		loop {
+			if let Some(index) = next {
+				return Some((owner, index));
+			}
+			owner = self.parent(owner)?;
+			next = self.table.get(owner.tag_number(), kind).copied();
+		}
+	}
+
+	/// Returns true if the state, or one of its ancestors, defers events of the given kind
+	pub(crate) fn defers(&self, state: S, kind: usize) -> bool {
+		self.ancestors(state).any(|s| self.record(s).is_some_and(|r| r.defers.contains(&kind)))
+	}
+
+	/// Iterates over the transitions leaving the state with the given tag, ordered by event then
+	/// the order they are tried
+	pub(crate) fn transitions_from(&self, state: usize) -> impl Iterator<Item = &Transition<S, K, A, P>> + '_ {
+		self.table.row(state)
+			.flat_map(move |(_, &head)| self.chain(Some(head)))
+			.map(move |index| &self.actions[index])
+	}
+
+	/// Returns true if the state with the given tag has any transitions for events
+	pub(crate) fn has_transitions(&self, state: usize) -> bool {
+		self.table.row(state).next().is_some()
+	}
+
+	/// Lists the states the chart has been told of, along with any others their type can list, in
+	/// tag order
+	pub(crate) fn states(&self) -> Vec<S> {
+		let mut states = BTreeMap::new();
+		for (_, record) in self.transitions.iter() {
+			let timeout = record.timeout.as_ref().map(|t| t.next_state);
+			for state in record.state.into_iter().chain(record.parent).chain(record.initial).chain(timeout) {
+				states.insert(state.tag_number(), state);
+			}
+		}
+		for transition in &self.actions {
+			states.insert(transition.next_state.tag_number(), transition.next_state);
+		}
+		for state in S::variants() {
+			states.entry(state.tag_number()).or_insert(state);
+		}
+		states.into_values().collect()
+	}
+
+	pub(crate) fn parent(&self, state: S) -> Option<S> {
+		self.record(state).and_then(|r| r.parent)
+	}
+
+	/// Iterates over the state and then each of its ancestors
+	pub(crate) fn ancestors(&self, state: S) -> impl Iterator<Item = S> + use<'_, S, K, B, A, P, X> {
+		iter::successors(Some(state), move |&s| self.parent(s))
+	}
+
+	pub(crate) fn is_descendant_or_self(&self, state: S, ancestor: S) -> bool {
+		self.ancestors(state).any(|s| s.tag_number() == ancestor.tag_number())
+	}
+
+	/// Finds the nearest state that is exited and re-entered by neither side of a transition from
+	/// one state to another, None meaning that every ancestor of both is exited and re-entered
+	pub(crate) fn common_ancestor(&self, from: S, to: S) -> Option<S> {
+		if self.is_descendant_or_self(from, to) {
+			return self.parent(to);
+		}
+		self.ancestors(from).find(|&candidate| self.is_descendant_or_self(to, candidate))
+	}
+
+	/// Finds the ancestor of the given state, or the state itself, that is a direct substate of
+	/// the one already entered, or a top level state if None has been entered
+	pub(crate) fn below(&self, entered: Option<S>, state: S) -> S {
+		let entered = entered.map(|s| s.tag_number());
+		let mut state = state;
+		while let Some(parent) = self.parent(state) {
+			if Some(parent.tag_number()) == entered {
+				break;
+			}
+			state = parent;
+		}
+		state
+	}
+
+	/// Finds the state the machine would rest in after a transition to the given state, by
+	/// following initial substates
+	pub(crate) fn resting_state(&self, state: S) -> S {
+		let mut state = state;
+		while let Some(substate) = self.record(state).and_then(|r| r.initial) {
+			state = substate;
+		}
+		state
+	}
+
+	/// Returns true if the state is the initial state, or has a transition leading to or from it
+	pub(crate) fn is_known_state(&self, state: S) -> bool {
+		let tag = state.tag_number();
+		if tag == self.initial_state.tag_number() {
+			return true;
+		}
+		if self.has_transitions(tag) || self.actions.iter().any(|t| t.next_state.tag_number() == tag) {
+			return true;
+		}
+		self.transitions.iter().any(|(source, record)| {
+			let leaves = source == tag && record.timeout.is_some();
+			let enters = record.timeout.as_ref().is_some_and(|t| t.next_state.tag_number() == tag)
+				|| record.initial.is_some_and(|s| s.tag_number() == tag);
+			leaves || enters
+		})
+	}
+}
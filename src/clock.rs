@@ -0,0 +1,105 @@
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::time::Instant;
+
+use super::shared::Shared;
+
+/// A Clock tells a Machine how much time has passed, as the time since some fixed starting point,
+/// so timed transitions know when they are due
+pub trait Clock {
+	/// returns the time elapsed since the clock's starting point
+	fn now(&self) -> Duration;
+}
+
+/// The SystemClock measures real time from the moment it was created, available with the `std`
+/// feature
+#[cfg(feature = "std")]
+#[derive(Copy, Clone, Debug)]
+pub struct SystemClock {
+	start: Instant,
+}
+
+#[cfg(feature = "std")]
+impl SystemClock {
+	/// Constructs a new clock starting now
+	pub fn new() -> SystemClock {
+		SystemClock {
+			start: Instant::now(),
+		}
+	}
+
+	/// Converts a time measured by this clock, such as a Machine's next deadline, into an Instant
+	pub fn instant(&self, at: Duration) -> Instant {
+		self.start + at
+	}
+}
+
+#[cfg(feature = "std")]
+impl Default for SystemClock {
+	fn default() -> SystemClock {
+		SystemClock::new()
+	}
+}
+
+#[cfg(feature = "std")]
+impl Clock for SystemClock {
+	fn now(&self) -> Duration {
+		self.start.elapsed()
+	}
+}
+
+/// The ManualClock only moves when told to, making timed transitions deterministic in tests.
+/// Clones share the same time, so one can be given to a Machine and another kept to move it. With
+/// the `std` feature it is `Send + Sync`, so it can also be given to a SyncMachine
+#[derive(Clone, Debug, Default)]
+pub struct ManualClock {
+	now: Shared<Duration>,
+}
+
+impl ManualClock {
+	/// Constructs a new clock at its starting point
+	pub fn new() -> ManualClock {
+		ManualClock::default()
+	}
+
+	/// Moves the clock forward by the given amount
+	pub fn advance(&self, by: Duration) {
+		self.now.with(|now| *now += by);
+	}
+
+	/// Sets the time elapsed since the clock's starting point
+	pub fn set(&self, now: Duration) {
+		self.now.with(|time| *time = now);
+	}
+}
+
+impl Clock for ManualClock {
+	fn now(&self) -> Duration {
+		self.now.with(|now| *now)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_manual_clock() {
+		let clock = ManualClock::new();
+		let shared = clock.clone();
+		assert_eq!(clock.now(), Duration::from_secs(0));
+		shared.advance(Duration::from_millis(1500));
+		assert_eq!(clock.now(), Duration::from_millis(1500));
+		shared.set(Duration::from_secs(1));
+		assert_eq!(clock.now(), Duration::from_secs(1));
+	}
+
+	#[test]
+	#[cfg(feature = "std")]
+	fn test_system_clock() {
+		let clock = SystemClock::new();
+		let before = clock.now();
+		assert!(clock.now() >= before);
+		assert_eq!(clock.instant(Duration::from_secs(2)) - clock.instant(Duration::from_secs(1)), Duration::from_secs(1));
+	}
+}
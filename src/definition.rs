@@ -0,0 +1,470 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use super::chart::Chart;
+use super::run::Run;
+#[cfg(feature = "std")]
+use super::SystemClock;
+use super::{
+	Clock, DenseStorage, EnumTag, EventKind, EventQueue, History, RestoreError, Snapshot, Storage,
+	SyncPredicate, TransitionOutcome, Validation,
+};
+
+/// Shared actions are actions that can be run by many machine instances at once, so they cannot
+/// mutate their own captured state, only the instance's context
+pub type SharedAction<'a, S, E, C = ()> = Box<dyn Fn(&S,&E,&mut C) + Send + Sync + 'a>;
+
+/// Shared state actions are state actions that can be run by many machine instances at once
+pub type SharedStateAction<'a, S, C = ()> = Box<dyn Fn(&S,&mut C) + Send + Sync + 'a>;
+
+/// The Chart of a MachineDefinition, whose closures are shared between its instances
+type DefinitionChart<'a, S, E, C, B> = Chart<S, <E as EventKind>::Kind, B, SharedAction<'a, S, E, C>, SyncPredicate<'a, S, E, C>, SharedStateAction<'a, S, C>>;
+
+/// The MachineDefinition holds everything a Machine is registered with, its transitions, state
+/// actions, timed transitions and state hierarchy, but none of its running state, so one
+/// definition, behind an `Arc` or a `&'static`, can drive any number of MachineInstances. Its
+/// closures are `Fn` rather than `FnMut` as they are shared between instances.
+///
+/// The definition also holds the clock timing how long its instances have been in each state.
+/// Definitions use a SystemClock by default, while without the `std` feature they have no clock,
+/// and their instances take no timed transitions, until one is set
+pub struct MachineDefinition<'a, S: EnumTag, E: EventKind, C = (), B: Storage = DenseStorage> {
+	chart: DefinitionChart<'a, S, E, C, B>,
+	clock: Option<Box<dyn Clock + Send + Sync + 'a>>,
+}
+
+/// The MachineInstance is one running copy of a MachineDefinition, holding its current state and
+/// context. Its posted and deferred events, the history of its states and when it entered its
+/// timed states are only allocated once it needs them, so an instance of a definition without
+/// history, timed transitions or deferred events is no more than its state and context. With the
+/// `std` feature instances are `Send`, so they can be handed between threads sharing the definition
+pub struct MachineInstance<S: EnumTag, E, C = (), B: Storage = DenseStorage> {
+	run: Run<S, E, C, B>,
+}
+
+impl<'a, S: EnumTag, E: EventKind, C> MachineDefinition<'a, S, E, C> {
+	/// Constructs a new definition whose instances start in the given initial state
+	pub fn new(initial_state: S) -> MachineDefinition<'a, S, E, C> {
+		MachineDefinition::with_storage(initial_state)
+	}
+}
+
+impl<'a, S: EnumTag, E: EventKind, C, B: Storage> MachineDefinition<'a, S, E, C, B> {
+	/// Constructs a new definition whose instances start in the given initial state, whose
+	/// transitions are held by the storage backend `B`
+	pub fn with_storage(initial_state: S) -> MachineDefinition<'a, S, E, C, B> {
+		#[cfg(feature = "std")]
+		let clock: Option<Box<dyn Clock + Send + Sync + 'a>> = Some(Box::new(SystemClock::new()));
+		#[cfg(not(feature = "std"))]
+		let clock: Option<Box<dyn Clock + Send + Sync + 'a>> = None;
+
+		MachineDefinition {
+			chart: Chart::new(initial_state),
+			clock,
+		}
+	}
+
+	/// Registers a new valid transition with the definition, as `Machine::add_transition`
+	pub fn add_transition<F>(&mut self, in_state: S, on_event: E::Kind, next_state: S, action: F) -> bool
+	where F: Fn(&S, &E) + Send + Sync + 'a {
+		self.add_transition_with_context(in_state, on_event, next_state, move |s, e, _| action(s, e))
+	}
+
+	/// Registers a new valid transition with the definition whose action can modify an instance's
+	/// context, as `Machine::add_transition_with_context`
+	pub fn add_transition_with_context<F>(&mut self, in_state: S, on_event: E::Kind, next_state: S, action: F) -> bool
+	where F: Fn(&S, &E, &mut C) + Send + Sync + 'a {
+		self.chart.add_transition(in_state, on_event, next_state, None, Box::new(action))
+	}
+
+	/// Registers a new transition with the definition that only occurs if the predicate returns
+	/// true, as `Machine::add_guarded_transition`
+	pub fn add_guarded_transition<P, F>(&mut self, in_state: S, on_event: E::Kind, next_state: S, predicate: P, action: F) -> bool
+	where P: Fn(&S, &E) -> bool + Send + Sync + 'a, F: Fn(&S, &E) + Send + Sync + 'a {
+		self.add_guarded_transition_with_context(
+			in_state, on_event, next_state,
+			move |s, e, _| predicate(s, e), move |s, e, _| action(s, e)
+		)
+	}
+
+	/// Registers a new guarded transition with the definition, as `add_guarded_transition`, whose
+	/// predicate can inspect an instance's context and whose action can modify it
+	pub fn add_guarded_transition_with_context<P, F>(&mut self, in_state: S, on_event: E::Kind, next_state: S, predicate: P, action: F) -> bool
+	where P: Fn(&S, &E, &C) -> bool + Send + Sync + 'a, F: Fn(&S, &E, &mut C) + Send + Sync + 'a {
+		self.chart.add_transition(in_state, on_event, next_state, Some(Box::new(predicate)), Box::new(action))
+	}
+
+	/// Sets the action performed whenever an instance enters the given state, replacing any
+	/// previous entry action for that state
+	pub fn set_on_enter<F>(&mut self, state: S, action: F)
+	where F: Fn(&S) + Send + Sync + 'a {
+		self.set_on_enter_with_context(state, move |s, _| action(s));
+	}
+
+	/// Sets the action performed whenever an instance enters the given state, as `set_on_enter`,
+	/// which can modify the instance's context
+	pub fn set_on_enter_with_context<F>(&mut self, state: S, action: F)
+	where F: Fn(&S, &mut C) + Send + Sync + 'a {
+		self.chart.record_mut(state).on_enter = Some(Box::new(action));
+	}
+
+	/// Sets the action performed whenever an instance exits the given state, replacing any
+	/// previous exit action for that state
+	pub fn set_on_exit<F>(&mut self, state: S, action: F)
+	where F: Fn(&S) + Send + Sync + 'a {
+		self.set_on_exit_with_context(state, move |s, _| action(s));
+	}
+
+	/// Sets the action performed whenever an instance exits the given state, as `set_on_exit`,
+	/// which can modify the instance's context
+	pub fn set_on_exit_with_context<F>(&mut self, state: S, action: F)
+	where F: Fn(&S, &mut C) + Send + Sync + 'a {
+		self.chart.record_mut(state).on_exit = Some(Box::new(action));
+	}
+
+	/// Registers a transition that instances take once they have been in the given state for the
+	/// given time, as `Machine::add_timed_transition`. Returns false if the state already has a
+	/// timed transition
+	pub fn add_timed_transition<F>(&mut self, in_state: S, after: Duration, next_state: S, action: F) -> bool
+	where F: Fn(&S) + Send + Sync + 'a {
+		self.add_timed_transition_with_context(in_state, after, next_state, move |s, _| action(s))
+	}
+
+	/// Registers a timed transition, as `add_timed_transition`, whose action can modify an
+	/// instance's context
+	pub fn add_timed_transition_with_context<F>(&mut self, in_state: S, after: Duration, next_state: S, action: F) -> bool
+	where F: Fn(&S, &mut C) + Send + Sync + 'a {
+		self.chart.add_timed_transition(in_state, after, next_state, Box::new(action))
+	}
+
+	/// Makes the given state a substate of the parent, as `Machine::set_parent`
+	pub fn set_parent(&mut self, state: S, parent: S) -> bool {
+		self.chart.set_parent(state, parent)
+	}
+
+	/// Sets the substate that is entered whenever a transition targets the parent state, as
+	/// `Machine::set_initial_substate`
+	pub fn set_initial_substate(&mut self, parent: S, substate: S) -> bool {
+		self.chart.set_initial_substate(parent, substate)
+	}
+
+	/// Defers events of the given kind while an instance is in the given state, as
+	/// `Machine::defer_event`
+	pub fn defer_event(&mut self, state: S, on_event: E::Kind) {
+		self.chart.defer_event(state, on_event);
+	}
+
+	/// Sets whether the given state resumes its previously active substate when it is re-entered,
+	/// each instance remembering its own
+	pub fn set_history(&mut self, state: S, history: History) {
+		self.chart.record_mut(state).history = history;
+	}
+
+	/// Marks the given state as final, so that `validate` expects it to have no way out
+	pub fn mark_final(&mut self, state: S) {
+		self.chart.record_mut(state).is_final = true;
+	}
+
+	/// Returns true if the given state has been marked as final
+	pub fn is_final(&self, state: S) -> bool {
+		self.chart.is_final(state)
+	}
+
+	/// Retrieves the parent of the given state, if it has one
+	pub fn parent(&self, state: S) -> Option<S> {
+		self.chart.parent(state)
+	}
+
+	/// Retrieves the state instances start in
+	pub fn initial_state(&self) -> S {
+		self.chart.initial_state
+	}
+
+	/// Checks the definition for unreachable states, dead ends and unhandled events, as
+	/// `Machine::validate`
+	pub fn validate(&self) -> Validation<S, E::Kind> {
+		self.chart.validate()
+	}
+
+	/// Replaces the clock used to time how long instances have been in each state. Instances
+	/// already made keep their entry times, which are now measured against the new clock
+	pub fn set_clock<T>(&mut self, clock: T)
+	where T: Clock + Send + Sync + 'a {
+		self.clock = Some(Box::new(clock));
+	}
+
+	/// Constructs a new instance in the initial state, owning the given context
+	pub fn instance(&self, context: C) -> MachineInstance<S, E, C, B> {
+		let epoch = match self.clock {
+			Some(ref clock) if self.chart.timed => clock.now(),
+			_ => Duration::ZERO,
+		};
+		MachineInstance {
+			run: Run::new(self.chart.initial_state, context, epoch),
+		}
+	}
+
+	/// Enters the initial substates of the state an instance was constructed in, as
+	/// `Machine::start`
+	pub fn start(&self, instance: &mut MachineInstance<S, E, C, B>) {
+		instance.run.start(&mut &self.chart, self.clock.as_deref());
+	}
+
+	/// Tick an instance with an Event, reporting whether it triggered a transition, as
+	/// `Machine::on_event`
+	pub fn on_event(&self, instance: &mut MachineInstance<S, E, C, B>, event_type: E) -> TransitionOutcome<S> {
+		instance.run.on_event(&mut &self.chart, self.clock.as_deref(), event_type)
+	}
+
+	/// Processes events waiting in an instance's event queue, up to its run limit, returning how
+	/// many were processed
+	pub fn process_pending(&self, instance: &mut MachineInstance<S, E, C, B>) -> usize {
+		instance.run.process_pending(&mut &self.chart, self.clock.as_deref())
+	}
+
+	/// Takes every timed transition of an instance that is due at the given time, as measured by
+	/// its clock, as `Machine::advance`
+	pub fn advance(&self, instance: &mut MachineInstance<S, E, C, B>, now: Duration) -> Vec<TransitionOutcome<S>> {
+		instance.run.advance(&mut &self.chart, self.clock.as_deref(), now)
+	}
+
+	/// Retrieves the time, as measured by an instance's clock, at which the earliest timed
+	/// transition of its current state or their ancestors is due
+	pub fn next_deadline(&self, instance: &MachineInstance<S, E, C, B>) -> Option<Duration> {
+		instance.run.next_timeout(&self.chart, self.clock.as_deref()).map(|(_, deadline)| deadline)
+	}
+
+	/// Returns true if the given state is an instance's current state or one of its ancestors
+	pub fn is_in(&self, instance: &MachineInstance<S, E, C, B>, state: S) -> bool {
+		self.chart.is_descendant_or_self(instance.run.state, state)
+	}
+
+	/// Takes a snapshot of an instance's current state and context, as `Machine::snapshot`
+	pub fn snapshot(&self, instance: &MachineInstance<S, E, C, B>) -> Snapshot<S, C>
+	where C: Clone {
+		instance.run.snapshot(&self.chart)
+	}
+
+	/// Restores an instance's current state and context from a snapshot, as `Machine::restore`
+	pub fn restore(&self, instance: &mut MachineInstance<S, E, C, B>, snapshot: Snapshot<S, C>) -> Result<(), RestoreError<S>> {
+		instance.run.restore(&self.chart, snapshot, self.clock.as_ref().map(|c| c.now()))
+	}
+}
+
+impl<S: EnumTag, E, C, B: Storage> MachineInstance<S, E, C, B> {
+	/// Retrieves the current state
+	pub fn current_state(&self) -> S {
+		self.run.state
+	}
+
+	/// Retrieves a reference to the instance's context
+	pub fn context(&self) -> &C {
+		&self.run.context
+	}
+
+	/// Retrieves a mutable reference to the instance's context
+	pub fn context_mut(&mut self) -> &mut C {
+		&mut self.run.context
+	}
+
+	/// Consumes the instance, returning its context
+	pub fn into_context(self) -> C {
+		self.run.context
+	}
+
+	/// Retrieves a handle to the instance's event queue, which actions can capture to post events
+	/// to the instance while it is handling another, as `Machine::event_queue`
+	pub fn event_queue(&mut self) -> EventQueue<E> {
+		self.run.event_queue()
+	}
+
+	/// Retrieves the number of posted events waiting to be processed
+	pub fn pending_events(&self) -> usize {
+		self.run.pending_events()
+	}
+
+	/// Retrieves the number of deferred events waiting to be replayed
+	pub fn deferred_events(&self) -> usize {
+		self.run.deferred_events()
+	}
+
+	/// Sets the maximum number of posted events processed at once, as `Machine::set_run_limit`
+	pub fn set_run_limit(&mut self, run_limit: usize) {
+		self.run.set_run_limit(run_limit);
+	}
+
+	/// Forgets which substate of the given state was last active, so it is next entered through
+	/// its initial substate
+	pub fn clear_history(&mut self, state: S) {
+		self.run.clear_history(state);
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::sync::Arc;
+	use std::thread;
+
+	use super::*;
+
+	#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+	enum SessionState {
+		Anonymous,
+		LoggedIn,
+	}
+
+	#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+	enum SessionEvent {
+		LogIn,
+		LogOut,
+	}
+
+	impl EnumTag for SessionState {
+		fn tag_number(&self) -> usize {
+			*self as usize
+		}
+		fn max_tag_number() -> usize {
+			SessionState::LoggedIn as usize
+		}
+	}
+
+	impl EnumTag for SessionEvent {
+		fn tag_number(&self) -> usize {
+			*self as usize
+		}
+		fn max_tag_number() -> usize {
+			SessionEvent::LogOut as usize
+		}
+	}
+
+	fn session<'a>() -> MachineDefinition<'a, SessionState, SessionEvent, u32> {
+		let mut definition = MachineDefinition::new(SessionState::Anonymous);
+		definition.add_guarded_transition_with_context(
+			SessionState::Anonymous, SessionEvent::LogIn, SessionState::LoggedIn,
+			|_, _, logins| *logins < 3, |_, _, _| {}
+		);
+		definition.add_transition(SessionState::LoggedIn, SessionEvent::LogOut, SessionState::Anonymous, |_, _| {});
+		definition.set_on_enter_with_context(SessionState::LoggedIn, |_, logins| *logins += 1);
+		definition
+	}
+
+	#[test]
+	fn test_instances() {
+		let definition = session();
+		let mut first = definition.instance(0);
+		let mut second = definition.instance(2);
+		assert!(definition.on_event(&mut first, SessionEvent::LogIn).is_handled());
+		assert!(definition.on_event(&mut second, SessionEvent::LogIn).is_handled());
+		assert!(definition.on_event(&mut second, SessionEvent::LogOut).is_handled());
+		assert!(!definition.on_event(&mut second, SessionEvent::LogIn).is_handled());
+		assert_eq!(first.current_state(), SessionState::LoggedIn);
+		assert_eq!(*first.context(), 1);
+		assert_eq!(second.current_state(), SessionState::Anonymous);
+		assert_eq!(second.into_context(), 3);
+	}
+
+	#[test]
+	fn test_instance_size() {
+		let definition = session();
+		let mut instance = definition.instance(0);
+		definition.on_event(&mut instance, SessionEvent::LogIn);
+		assert_eq!(instance.current_state(), SessionState::LoggedIn);
+		assert!(core::mem::size_of::<MachineInstance<SessionState, SessionEvent, u32>>() <= 3 * core::mem::size_of::<usize>());
+	}
+
+	#[test]
+	fn test_shared_definition() {
+		let definition = Arc::new(session());
+		let workers: Vec<_> = (0..4).map(|_| {
+			let definition = definition.clone();
+			thread::spawn(move || {
+				let mut instances: Vec<_> = (0..100).map(|_| definition.instance(0)).collect();
+				for instance in &mut instances {
+					definition.on_event(instance, SessionEvent::LogIn);
+				}
+				instances.iter().all(|instance| instance.current_state() == SessionState::LoggedIn)
+			})
+		}).collect();
+		for worker in workers {
+			assert!(worker.join().unwrap());
+		}
+	}
+
+	#[cfg(feature = "std")]
+	#[test]
+	fn test_instance_substates_and_timers() {
+		use super::super::ManualClock;
+
+		#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+		enum KioskState {
+			Idle,
+			Session,
+			Browsing,
+			Paying,
+		}
+
+		#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+		enum KioskEvent {
+			Touch,
+			Pay,
+			Paid,
+		}
+
+		impl EnumTag for KioskState {
+			fn tag_number(&self) -> usize {
+				*self as usize
+			}
+			fn max_tag_number() -> usize {
+				KioskState::Paying as usize
+			}
+		}
+
+		impl EnumTag for KioskEvent {
+			fn tag_number(&self) -> usize {
+				*self as usize
+			}
+			fn max_tag_number() -> usize {
+				KioskEvent::Paid as usize
+			}
+		}
+
+		let mut definition: MachineDefinition<KioskState, KioskEvent, Vec<KioskState>> = MachineDefinition::new(KioskState::Idle);
+		definition.add_transition(KioskState::Idle, KioskEvent::Touch, KioskState::Session, |_, _| {});
+		definition.add_transition(KioskState::Browsing, KioskEvent::Pay, KioskState::Paying, |_, _| {});
+		definition.add_transition(KioskState::Paying, KioskEvent::Paid, KioskState::Browsing, |_, _| {});
+		definition.add_timed_transition(KioskState::Session, Duration::from_secs(30), KioskState::Idle, |_| {});
+		definition.set_parent(KioskState::Browsing, KioskState::Session);
+		definition.set_parent(KioskState::Paying, KioskState::Session);
+		definition.set_initial_substate(KioskState::Session, KioskState::Browsing);
+		definition.defer_event(KioskState::Paying, KioskEvent::Pay);
+		definition.set_on_enter_with_context(KioskState::Browsing, |s, entered| entered.push(*s));
+		let clock = ManualClock::new();
+		definition.set_clock(clock.clone());
+		let definition = Arc::new(definition);
+
+		let mut instance = definition.instance(Vec::new());
+		let worker = {
+			let definition = definition.clone();
+			thread::spawn(move || {
+				definition.on_event(&mut instance, KioskEvent::Touch);
+				assert!(definition.is_in(&instance, KioskState::Session));
+				definition.on_event(&mut instance, KioskEvent::Pay);
+				assert_eq!(definition.on_event(&mut instance, KioskEvent::Pay), TransitionOutcome::Deferred { state: KioskState::Paying });
+				definition.on_event(&mut instance, KioskEvent::Paid);
+				instance
+			})
+		};
+		let mut instance = worker.join().unwrap();
+		assert_eq!(instance.current_state(), KioskState::Paying);
+		assert_eq!(*instance.context(), [KioskState::Browsing, KioskState::Browsing]);
+
+		let other = definition.instance(Vec::new());
+		assert_eq!(other.current_state(), KioskState::Idle);
+
+		clock.advance(Duration::from_secs(30));
+		assert_eq!(definition.next_deadline(&instance), Some(Duration::from_secs(30)));
+		let outcomes = definition.advance(&mut instance, clock.now());
+		assert_eq!(outcomes, vec![TransitionOutcome::Handled { previous: KioskState::Paying, current: KioskState::Idle }]);
+	}
+}
@@ -0,0 +1,84 @@
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use super::chart::Chart;
+use super::{EnumTag, Storage, TagMap};
+
+/// The Trigger is what causes a transition to be taken
+pub(crate) enum Trigger<K> {
+	/// an event of the given kind
+	Event(K),
+	/// the machine having been in the state for the given time
+	After(Duration),
+}
+
+/// The TransitionInfo describes one of a machine's transitions, for exporting and analysing it
+pub(crate) struct TransitionInfo<S, K> {
+	pub(crate) from: S,
+	pub(crate) trigger: Trigger<K>,
+	pub(crate) to: S,
+	pub(crate) guarded: bool,
+}
+
+/// The StateInfo describes one of a machine's states, for exporting and analysing it
+pub(crate) struct StateInfo<S> {
+	pub(crate) state: S,
+	pub(crate) parent: Option<S>,
+	pub(crate) initial: Option<S>,
+	pub(crate) has_entry: bool,
+	pub(crate) has_exit: bool,
+}
+
+impl<S: EnumTag, K: EnumTag, B: Storage, A, P, X> Chart<S, K, B, A, P, X> {
+	/// Describes every state the machine knows of, in tag order
+	pub(crate) fn state_infos(&self) -> Vec<StateInfo<S>> {
+		self.states().into_iter().map(|state| {
+			match self.record(state) {
+				Some(record) => StateInfo {
+					state,
+					parent: record.parent,
+					initial: record.initial,
+					has_entry: record.on_enter.is_some(),
+					has_exit: record.on_exit.is_some(),
+				},
+				None => StateInfo {
+					state,
+					parent: None,
+					initial: None,
+					has_entry: false,
+					has_exit: false,
+				},
+			}
+		}).collect()
+	}
+
+	/// Describes every transition, ordered by the tags of their states then events, transitions
+	/// for the same state and event staying in the order they are tried, each state's timed
+	/// transition coming last
+	pub(crate) fn transition_infos(&self) -> Vec<TransitionInfo<S, K>> {
+		let mut infos = Vec::new();
+		for (tag, record) in self.transitions.iter() {
+			let from = match record.state {
+				Some(state) => state,
+				None => continue,
+			};
+			for t in self.transitions_from(tag) {
+				infos.push(TransitionInfo {
+					from,
+					trigger: Trigger::Event(t.event),
+					to: t.next_state,
+					guarded: t.predicate.is_some(),
+				});
+			}
+			if let Some(ref timeout) = record.timeout {
+				infos.push(TransitionInfo {
+					from,
+					trigger: Trigger::After(timeout.after),
+					to: timeout.next_state,
+					guarded: false,
+				});
+			}
+		}
+		infos
+	}
+}
@@ -0,0 +1,129 @@
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::Debug;
+
+use super::describe::{StateInfo, Trigger, TransitionInfo};
+
+/// The DiagramOptions control how a Machine is drawn by `to_dot`, `to_mermaid` and `to_plantuml`
+pub struct DiagramOptions<'l, S, K> {
+	pub(crate) name: String,
+	pub(crate) highlight_current: bool,
+	pub(crate) state_label: Box<dyn Fn(&S) -> String + 'l>,
+	pub(crate) event_label: Box<dyn Fn(&K) -> String + 'l>,
+}
+
+impl<'l, S, K> DiagramOptions<'l, S, K> {
+	/// Constructs options that label states and events with the given functions
+	pub fn new<F, G>(state_label: F, event_label: G) -> DiagramOptions<'l, S, K>
+	where F: Fn(&S) -> String + 'l, G: Fn(&K) -> String + 'l {
+		DiagramOptions {
+			name: "fsm".to_string(),
+			highlight_current: false,
+			state_label: Box::new(state_label),
+			event_label: Box::new(event_label),
+		}
+	}
+
+	/// Sets the name of the diagram, defaults to "fsm"
+	pub fn name(mut self, name: &str) -> DiagramOptions<'l, S, K> {
+		self.name = name.to_string();
+		self
+	}
+
+	/// Sets whether the machine's current state is filled in, defaults to false
+	pub fn highlight_current(mut self, highlight_current: bool) -> DiagramOptions<'l, S, K> {
+		self.highlight_current = highlight_current;
+		self
+	}
+}
+
+impl<'l, S: Debug, K: Debug> DiagramOptions<'l, S, K> {
+	/// Constructs options that label states and events with their Debug representation
+	pub fn debug() -> DiagramOptions<'l, S, K> {
+		DiagramOptions::new(|state| format!("{:?}", state), |event| format!("{:?}", event))
+	}
+}
+
+/// Labels a transition with its event, or how long it waits, marking it if it is guarded
+pub(crate) fn transition_label<S, K>(info: &TransitionInfo<S, K>, options: &DiagramOptions<S, K>) -> String {
+	let mut label = match info.trigger {
+		Trigger::Event(ref event) => (options.event_label)(event),
+		Trigger::After(after) => format!("after {:?}", after),
+	};
+	if info.guarded {
+		label.push_str(" [guarded]");
+	}
+	label
+}
+
+/// Lists the descriptions a state diagram gives a state, for the actions it runs
+pub(crate) fn state_descriptions<S>(info: &StateInfo<S>) -> Vec<&'static str> {
+	let mut descriptions = Vec::new();
+	if info.has_entry {
+		descriptions.push("on entry");
+	}
+	if info.has_exit {
+		descriptions.push("on exit");
+	}
+	descriptions
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+	use std::time::Duration;
+
+	use super::super::{EnumTag, Machine, ManualClock};
+
+	#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+	pub(crate) enum LightState {
+		Off,
+		On,
+		Broken,
+		Dim,
+		Bright,
+	}
+
+	#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+	pub(crate) enum LightEvent {
+		Toggle,
+		Dial,
+	}
+
+	impl EnumTag for LightState {
+		fn tag_number(&self) -> usize {
+			*self as usize
+		}
+		fn max_tag_number() -> usize {
+			LightState::Bright as usize
+		}
+	}
+
+	impl EnumTag for LightEvent {
+		fn tag_number(&self) -> usize {
+			*self as usize
+		}
+		fn max_tag_number() -> usize {
+			LightEvent::Dial as usize
+		}
+	}
+
+	/// A light that can be dimmed while it is on, and which eventually burns out
+	pub(crate) fn light<'a>() -> Machine<'a, LightState, LightEvent> {
+		let mut machine = Machine::new(LightState::Off);
+		machine.set_clock(ManualClock::new());
+		machine.add_guarded_transition(LightState::Off, LightEvent::Toggle, LightState::On, |_,_| true, |_,_| {});
+		machine.add_transition(LightState::Off, LightEvent::Toggle, LightState::Off, |_,_| {});
+		machine.add_transition(LightState::On, LightEvent::Toggle, LightState::Off, |_,_| {});
+		machine.add_timed_transition(LightState::On, Duration::from_secs(3600), LightState::Broken, |_| {});
+		machine.add_transition(LightState::Dim, LightEvent::Dial, LightState::Bright, |_,_| {});
+		machine.add_transition(LightState::Bright, LightEvent::Dial, LightState::Dim, |_,_| {});
+		machine.set_parent(LightState::Dim, LightState::On);
+		machine.set_parent(LightState::Bright, LightState::On);
+		machine.set_initial_substate(LightState::On, LightState::Dim);
+		machine.set_on_enter(LightState::On, |_| {});
+		machine.set_on_exit(LightState::On, |_| {});
+		machine
+	}
+}
@@ -0,0 +1,81 @@
+use alloc::format;
+use alloc::string::String;
+use core::fmt::Write;
+
+use super::diagram::{transition_label, DiagramOptions};
+use super::{Closures, EnumTag, EventKind, StateMachine, Storage};
+
+impl<'a, S: EnumTag, E: EventKind, C, B: Storage, K: Closures<'a, S, E, C>> StateMachine<'a, S, E, C, B, K> {
+	/// Draws the machine's transitions as a Graphviz DOT graph, with a node for each state and an
+	/// edge for each transition, guarded transitions being marked as such. The initial state is
+	/// pointed to by an unlabelled edge
+	pub fn to_dot(&self, options: &DiagramOptions<S, E::Kind>) -> String {
+		let mut dot = String::new();
+		writeln!(dot, "digraph {} {{", quote(&options.name)).unwrap();
+		writeln!(dot, "\t__start [shape=point];").unwrap();
+		for info in self.chart.state_infos() {
+			let tag = info.state.tag_number();
+			let label = quote(&(options.state_label)(&info.state));
+			if options.highlight_current && tag == self.run.state.tag_number() {
+				writeln!(dot, "\ts{} [label={}, style=filled, fillcolor=lightgrey];", tag, label).unwrap();
+			} else {
+				writeln!(dot, "\ts{} [label={}];", tag, label).unwrap();
+			}
+		}
+		writeln!(dot, "\t__start -> s{};", self.chart.initial_state.tag_number()).unwrap();
+		for info in self.chart.transition_infos() {
+			let label = quote(&transition_label(&info, options));
+			writeln!(dot, "\ts{} -> s{} [label={}];", info.from.tag_number(), info.to.tag_number(), label).unwrap();
+		}
+		dot.push_str("}\n");
+		dot
+	}
+}
+
+/// Quotes a DOT identifier, escaping any quotes and backslashes within it
+fn quote(text: &str) -> String {
+	format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use super::super::diagram::test::{light, LightEvent, LightState};
+
+	#[test]
+	fn test_to_dot() {
+		let mut machine = light();
+		machine.on_event(LightEvent::Toggle);
+		assert_eq!(machine.to_dot(&DiagramOptions::debug()), "\
+digraph \"fsm\" {
+	__start [shape=point];
+	s0 [label=\"Off\"];
+	s1 [label=\"On\"];
+	s2 [label=\"Broken\"];
+	s3 [label=\"Dim\"];
+	s4 [label=\"Bright\"];
+	__start -> s0;
+	s0 -> s1 [label=\"Toggle [guarded]\"];
+	s0 -> s0 [label=\"Toggle\"];
+	s1 -> s0 [label=\"Toggle\"];
+	s1 -> s2 [label=\"after 3600s\"];
+	s3 -> s4 [label=\"Dial\"];
+	s4 -> s3 [label=\"Dial\"];
+}
+");
+	}
+
+	#[test]
+	fn test_to_dot_options() {
+		let mut machine = light();
+		machine.on_event(LightEvent::Toggle);
+		let options = DiagramOptions::new(|state: &LightState| format!("\"{:?}\"", state).to_lowercase(), |_: &LightEvent| "flick".to_string())
+			.name("lamp")
+			.highlight_current(true);
+		let dot = machine.to_dot(&options);
+		assert!(dot.starts_with("digraph \"lamp\" {\n"));
+		assert!(dot.contains("\ts3 [label=\"\\\"dim\\\"\", style=filled, fillcolor=lightgrey];\n"));
+		assert!(dot.contains("\ts0 [label=\"\\\"off\\\"\"];\n"));
+		assert!(dot.contains("\ts1 -> s0 [label=\"flick\"];\n"));
+	}
+}
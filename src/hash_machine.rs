@@ -0,0 +1,274 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::hash::Hash;
+use core::time::Duration;
+use std::collections::HashMap;
+
+use super::chart::Chart;
+use super::run::{Run, Runner};
+use super::{Action, Clock, EnumTag, HashStorage, Predicate, StateAction, TransitionOutcome};
+
+/// The Tag stands in for one of a HashMachine's states or events in its Chart, being the order it
+/// was first seen in
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+struct Tag(usize);
+
+impl EnumTag for Tag {
+	fn tag_number(&self) -> usize {
+		self.0
+	}
+
+	fn max_tag_number() -> usize {
+		usize::MAX
+	}
+
+	fn from_tag_number(tag: usize) -> Option<Tag> {
+		Some(Tag(tag))
+	}
+}
+
+/// The Chart of a HashMachine, keyed by the Tags of its states and events
+type HashChart<'a, S, E, C> = Chart<Tag, Tag, HashStorage, Action<'a, S, E, C>, Predicate<'a, S, E, C>, StateAction<'a, S, C>>;
+
+/// The Interned runs a HashMachine's Chart, handing its closures the states they were registered
+/// with rather than their Tags
+struct Interned<'m, 'a, S, E, C> {
+	chart: &'m mut HashChart<'a, S, E, C>,
+	states: &'m [S],
+	events: &'m HashMap<E, Tag>,
+}
+
+impl<'a, S, E: Hash + Eq, C> Runner<Tag, E, C, HashStorage> for Interned<'_, 'a, S, E, C> {
+	type Kind = Tag;
+	type Action = Action<'a, S, E, C>;
+	type Predicate = Predicate<'a, S, E, C>;
+	type StateAction = StateAction<'a, S, C>;
+
+	fn chart(&self) -> &HashChart<'a, S, E, C> {
+		self.chart
+	}
+
+	fn kind(&self, event: &E) -> Option<usize> {
+		self.events.get(event).map(|tag| tag.0)
+	}
+
+	fn passes(&self, index: usize, state: &Tag, event: &E, context: &C) -> bool {
+		match self.chart.actions[index].predicate {
+			Some(ref predicate) => predicate(&self.states[state.0], event, context),
+			None => true,
+		}
+	}
+
+	fn act(&mut self, index: usize, state: &Tag, event: &E, context: &mut C) {
+		(self.chart.actions[index].action)(&self.states[state.0], event, context);
+	}
+
+	fn exit(&mut self, state: &Tag, context: &mut C) {
+		if let Some(on_exit) = self.chart.on_exit_mut(*state) {
+			on_exit(&self.states[state.0], context);
+		}
+	}
+
+	fn enter(&mut self, state: &Tag, context: &mut C) {
+		if let Some(on_enter) = self.chart.on_enter_mut(*state) {
+			on_enter(&self.states[state.0], context);
+		}
+	}
+
+	fn time_out(&mut self, timed: Tag, state: &Tag, context: &mut C) {
+		if let Some(action) = self.chart.timeout_action_mut(timed) {
+			action(&self.states[state.0], context);
+		}
+	}
+}
+
+/// The HashMachine is a Finite State Machine whose states and events can be any `Hash + Eq +
+/// Clone` type, such as strings, integers or tuples, rather than enums implementing EnumTag, so
+/// machines can be loaded from configuration or generated at runtime. Each state and event is
+/// given a Tag the first time it is seen and the machine is then run like any other. It offers
+/// transitions and state actions, but not the state hierarchy, event queue or timers of a
+/// Machine, and is available with the `std` feature
+pub struct HashMachine<'a, S, E, C = ()> {
+	chart: HashChart<'a, S, E, C>,
+	run: Run<Tag, E, C, HashStorage>,
+	states: Vec<S>,
+	state_tags: HashMap<S, Tag>,
+	events: HashMap<E, Tag>,
+}
+
+impl<'a, S: Hash + Eq + Clone, E: Hash + Eq + Clone> HashMachine<'a, S, E> {
+	/// Constructs a new FSM with a given initial state
+	pub fn new(initial_state: S) -> HashMachine<'a, S, E> {
+		HashMachine::with_context(initial_state, ())
+	}
+}
+
+impl<'a, S: Hash + Eq + Clone, E: Hash + Eq + Clone, C> HashMachine<'a, S, E, C> {
+	/// Constructs a new FSM with a given initial state, owning the given context
+	pub fn with_context(initial_state: S, context: C) -> HashMachine<'a, S, E, C> {
+		let mut state_tags = HashMap::new();
+		state_tags.insert(initial_state.clone(), Tag(0));
+		HashMachine {
+			chart: Chart::new(Tag(0)),
+			run: Run::new(Tag(0), context, Duration::ZERO),
+			states: vec![initial_state],
+			state_tags,
+			events: HashMap::new(),
+		}
+	}
+
+	/// Finds the Tag of the state, giving it the next one if it hasn't been seen before
+	fn state_tag(&mut self, state: S) -> Tag {
+		if let Some(&tag) = self.state_tags.get(&state) {
+			return tag;
+		}
+		let tag = Tag(self.states.len());
+		self.states.push(state.clone());
+		self.state_tags.insert(state, tag);
+		tag
+	}
+
+	/// Finds the Tag of the event, giving it the next one if it hasn't been seen before
+	fn event_tag(&mut self, event: E) -> Tag {
+		let next = Tag(self.events.len());
+		*self.events.entry(event).or_insert(next)
+	}
+
+	/// Registers a new valid transition with the FSM, returns false if the state already has an
+	/// unconditional transition for this event
+	pub fn add_transition<F>(&mut self, in_state: S, on_event: E, next_state: S, mut action: F) -> bool
+	where F: FnMut(&S, &E) + 'a {
+		self.add_transition_with_context(in_state, on_event, next_state, move |s, e, _| action(s, e))
+	}
+
+	/// Registers a new valid transition with the FSM whose action can modify the machine's context,
+	/// returns false if the state already has an unconditional transition for this event
+	pub fn add_transition_with_context<F>(&mut self, in_state: S, on_event: E, next_state: S, action: F) -> bool
+	where F: FnMut(&S, &E, &mut C) + 'a {
+		let (in_state, on_event, next_state) = (self.state_tag(in_state), self.event_tag(on_event), self.state_tag(next_state));
+		self.chart.add_transition(in_state, on_event, next_state, None, Box::new(action))
+	}
+
+	/// Registers a new transition with the FSM that only occurs if the predicate returns true,
+	/// transitions for the same state and event are tried in the order they were added and the
+	/// first whose predicate passes is taken. Returns false if the state already has an
+	/// unconditional transition for this event, as the new transition could never be taken
+	pub fn add_guarded_transition<P, F>(&mut self, in_state: S, on_event: E, next_state: S, predicate: P, mut action: F) -> bool
+	where P: Fn(&S, &E) -> bool + 'a, F: FnMut(&S, &E) + 'a {
+		self.add_guarded_transition_with_context(
+			in_state, on_event, next_state,
+			move |s, e, _| predicate(s, e), move |s, e, _| action(s, e)
+		)
+	}
+
+	/// Registers a new guarded transition with the FSM, as `add_guarded_transition`, whose
+	/// predicate can inspect the machine's context and whose action can modify it
+	pub fn add_guarded_transition_with_context<P, F>(&mut self, in_state: S, on_event: E, next_state: S, predicate: P, action: F) -> bool
+	where P: Fn(&S, &E, &C) -> bool + 'a, F: FnMut(&S, &E, &mut C) + 'a {
+		let (in_state, on_event, next_state) = (self.state_tag(in_state), self.event_tag(on_event), self.state_tag(next_state));
+		self.chart.add_transition(in_state, on_event, next_state, Some(Box::new(predicate)), Box::new(action))
+	}
+
+	/// Sets the action performed whenever the machine enters the given state, replacing any
+	/// previous entry action for that state
+	pub fn set_on_enter<F>(&mut self, state: S, mut action: F)
+	where F: FnMut(&S) + 'a {
+		self.set_on_enter_with_context(state, move |s, _| action(s));
+	}
+
+	/// Sets the action performed whenever the machine enters the given state, as `set_on_enter`,
+	/// which can modify the machine's context
+	pub fn set_on_enter_with_context<F>(&mut self, state: S, action: F)
+	where F: FnMut(&S, &mut C) + 'a {
+		let state = self.state_tag(state);
+		self.chart.record_mut(state).on_enter = Some(Box::new(action));
+	}
+
+	/// Sets the action performed whenever the machine exits the given state, replacing any
+	/// previous exit action for that state
+	pub fn set_on_exit<F>(&mut self, state: S, mut action: F)
+	where F: FnMut(&S) + 'a {
+		self.set_on_exit_with_context(state, move |s, _| action(s));
+	}
+
+	/// Sets the action performed whenever the machine exits the given state, as `set_on_exit`,
+	/// which can modify the machine's context
+	pub fn set_on_exit_with_context<F>(&mut self, state: S, action: F)
+	where F: FnMut(&S, &mut C) + 'a {
+		let state = self.state_tag(state);
+		self.chart.record_mut(state).on_exit = Some(Box::new(action));
+	}
+
+	/// Retrieves a reference to the current state
+	pub fn current_state(&self) -> &S {
+		&self.states[self.run.state.0]
+	}
+
+	/// Retrieves a reference to the machine's context
+	pub fn context(&self) -> &C {
+		&self.run.context
+	}
+
+	/// Retrieves a mutable reference to the machine's context
+	pub fn context_mut(&mut self) -> &mut C {
+		&mut self.run.context
+	}
+
+	/// Tick the State Machine with an Event, reporting whether it triggered a transition, which is
+	/// chosen and run as for a Machine. Events the machine has no transitions for are unhandled
+	pub fn on_event(&mut self, event_type: E) -> TransitionOutcome<S> {
+		let mut runner = Interned {
+			chart: &mut self.chart,
+			states: &self.states,
+			events: &self.events,
+		};
+		let states = &self.states;
+		self.run.on_event(&mut runner, None::<&dyn Clock>, event_type).map(|state| states[state.0].clone())
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+	enum Phase {
+		Warmup,
+		Main,
+	}
+
+	#[test]
+	fn test_string_machine() {
+		let mut machine = HashMachine::with_context("idle".to_string(), Vec::new());
+		machine.add_transition("idle".to_string(), "start".to_string(), "running".to_string(), |_,_| {});
+		machine.add_transition("running".to_string(), "stop".to_string(), "idle".to_string(), |_,_| {});
+		machine.set_on_enter_with_context("running".to_string(), |s, log: &mut Vec<String>| log.push(format!("enter {}", s)));
+		machine.set_on_exit_with_context("running".to_string(), |s, log: &mut Vec<String>| log.push(format!("exit {}", s)));
+		assert!(!machine.on_event("stop".to_string()).is_handled());
+		assert!(!machine.on_event("pause".to_string()).is_handled());
+		assert!(machine.on_event("start".to_string()).is_handled());
+		assert_eq!(machine.current_state(), "running");
+		assert!(machine.on_event("stop".to_string()).is_handled());
+		assert_eq!(machine.context(), &vec!["enter running".to_string(), "exit running".to_string()]);
+	}
+
+	#[test]
+	fn test_tuple_states() {
+		let mut machine = HashMachine::with_context((Phase::Warmup, 0u8), 0u32);
+		for round in 0..3u8 {
+			machine.add_transition((Phase::Warmup, round), 1, (Phase::Warmup, round + 1), |_,_| {});
+		}
+		machine.add_guarded_transition_with_context(
+			(Phase::Warmup, 3), 1, (Phase::Main, 0),
+			|_, _, laps| *laps >= 3, |_, _, laps| *laps = 0
+		);
+		machine.set_on_exit_with_context((Phase::Warmup, 0), |_, laps| *laps += 1);
+		machine.set_on_exit_with_context((Phase::Warmup, 1), |_, laps| *laps += 1);
+		machine.set_on_exit_with_context((Phase::Warmup, 2), |_, laps| *laps += 1);
+		for _ in 0..4 {
+			machine.on_event(1);
+		}
+		assert_eq!(machine.current_state(), &(Phase::Main, 0));
+		assert_eq!(*machine.context(), 0);
+	}
+}
@@ -1,9 +1,79 @@
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
 
-/// Actions are just boxed immutable functions that take an argument of the event that triggered them
-pub type Action<'a, S, E> = Box<Fn(&S,&E) + 'a>;
+extern crate alloc;
 
-/// Predicates are used to filter down whether a transition can occur
-pub type Predicate<'a, S, E> = Box<Fn(&S,&E) -> bool + 'a>;
+#[cfg(feature = "derive")]
+extern crate fsm_derive;
+
+/// Derives `EnumTag` for fieldless enums, available with the `derive` feature
+#[cfg(feature = "derive")]
+pub use fsm_derive::EnumTag;
+
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::iter;
+use core::time::Duration;
+
+use chart::Chart;
+use run::Run;
+use shared::Shared;
+
+#[cfg(feature = "async")]
+mod async_machine;
+mod builder;
+mod chart;
+mod clock;
+mod definition;
+mod describe;
+mod diagram;
+mod dot;
+#[cfg(feature = "std")]
+mod hash_machine;
+mod mermaid;
+mod parallel;
+mod plantuml;
+mod run;
+mod shared;
+mod snapshot;
+mod storage;
+mod sync_machine;
+mod validate;
+
+#[cfg(feature = "async")]
+pub use async_machine::{
+	AsyncAction, AsyncMachine, AsyncPredicate, AsyncStateAction, LocalAsync, SendAsync, SendAsyncAction,
+	SendAsyncMachine, SendAsyncPredicate, SendAsyncStateAction,
+};
+pub use builder::{BuildError, MachineBuilder, StateMachineBuilder, SyncMachineBuilder};
+pub use clock::{Clock, ManualClock};
+#[cfg(feature = "std")]
+pub use clock::SystemClock;
+pub use definition::{MachineDefinition, MachineInstance, SharedAction, SharedStateAction};
+pub use diagram::DiagramOptions;
+#[cfg(feature = "std")]
+pub use hash_machine::HashMachine;
+pub use parallel::{ParallelMachine, Region, Regions};
+pub use snapshot::{RestoreError, Snapshot};
+pub use storage::{DenseMap, DenseStorage, DenseTable, SortedMap, SortedStorage, SortedTable, Storage, TagMap, TagTable};
+#[cfg(feature = "std")]
+pub use storage::HashStorage;
+pub use sync_machine::{Sendable, SyncAction, SyncMachine, SyncPredicate, SyncStateAction};
+#[cfg(feature = "std")]
+pub use sync_machine::SharedMachine;
+pub use validate::Validation;
+
+/// Actions are just boxed functions that take an argument of the event that triggered them, along
+/// with mutable access to the machine's context, they may also mutate their own captured state
+pub type Action<'a, S, E, C = ()> = Box<dyn FnMut(&S,&E,&mut C) + 'a>;
+
+/// Predicates are used to filter down whether a transition can occur, they can inspect the
+/// machine's context but not modify it
+pub type Predicate<'a, S, E, C = ()> = Box<dyn Fn(&S,&E,&C) -> bool + 'a>;
+
+/// State actions are run when a state is entered or exited, they take an argument of that state
+/// along with mutable access to the machine's context
+pub type StateAction<'a, S, C = ()> = Box<dyn FnMut(&S,&mut C) + 'a>;
 
 /// Trait that should be trivially implementable for any C-Like Enum type
 pub trait EnumTag: Copy {
@@ -11,81 +81,508 @@ pub trait EnumTag: Copy {
 	fn tag_number(&self) -> usize;
 	/// returns the highest discriminator tag for this enum
 	fn max_tag_number() -> usize;
+	/// returns the enum value with the given discriminator tag, if there is one, the default returns
+	/// None
+	fn from_tag_number(_tag: usize) -> Option<Self> {
+		None
+	}
+	/// returns every value of the enum. This is only used to describe machines, such as when
+	/// exporting diagrams or validating them, so the default returns none
+	fn variants() -> impl Iterator<Item = Self> {
+		iter::empty()
+	}
 }
 
-/// The Transition records, for a given current state, what event type triggers it to move to
-/// what state, performing a specific action on the transition, filterable by a predicate function
-struct Transition<'a, S: EnumTag, E: EnumTag> {
-	next_state: S,
-	action: Action<'a, S, E>,
+/// Trait for event types that are dispatched on a kind rather than on the event itself, this
+/// lets events carry data (such as a received buffer) which predicates and actions receive by
+/// reference. Every EnumTag type is already its own kind
+pub trait EventKind {
+	/// the C-Like Enum that transitions are registered against
+	type Kind: EnumTag;
+	/// returns the kind of this event
+	fn kind(&self) -> Self::Kind;
 }
 
-/// The StateTransition records all Transitions for a given state
-struct StateTransitions<'a, S: EnumTag, E: EnumTag> {
-	edges: Vec<Option<Transition<'a, S, E>>>,
+impl<T: EnumTag> EventKind for T {
+	type Kind = T;
+
+	fn kind(&self) -> T {
+		*self
+	}
 }
 
-/// The Machine is the Finite State Machine, which has a current state and set of all valid
-/// transitions
-pub struct Machine<'a, S: EnumTag, E: EnumTag> {
-	state: S,
-	transitions: Vec<StateTransitions<'a, S, E>>,
+/// The TransitionOutcome reports what the Machine did with an Event
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TransitionOutcome<S> {
+	/// The event triggered a transition, moving the machine from the previous state to the new one
+	Handled {
+		previous: S,
+		current: S,
+	},
+	/// There was no transition for the event in the current state, or none of their predicates
+	/// passed, so the event was dropped
+	Unhandled {
+		state: S,
+	},
+	/// There was no transition for the event in the current state, but the state defers events of
+	/// its kind, so the event was held back to be replayed after the next transition
+	Deferred {
+		state: S,
+	},
 }
 
-impl<'a, S: EnumTag, E: EnumTag> Machine<'a, S, E> {
-	/// Constructs a new FSM with a given initial state
-	pub fn new(initial_state: S) -> Machine<'a, S, E> {
-		let mut transitions = Vec::with_capacity(S::max_tag_number());
+impl<S> TransitionOutcome<S> {
+	/// Returns true if the event triggered a transition
+	pub fn is_handled(&self) -> bool {
+		match *self {
+			TransitionOutcome::Handled { .. } => true,
+			TransitionOutcome::Unhandled { .. } | TransitionOutcome::Deferred { .. } => false,
+		}
+	}
+
+	/// Converts the states the outcome names
+	#[cfg(feature = "std")]
+	pub(crate) fn map<T, F: FnMut(S) -> T>(self, mut f: F) -> TransitionOutcome<T> {
+		match self {
+			TransitionOutcome::Handled { previous, current } => TransitionOutcome::Handled {
+				previous: f(previous),
+				current: f(current),
+			},
+			TransitionOutcome::Unhandled { state } => TransitionOutcome::Unhandled {
+				state: f(state),
+			},
+			TransitionOutcome::Deferred { state } => TransitionOutcome::Deferred {
+				state: f(state),
+			},
+		}
+	}
+}
 
-		for _ in 0..S::max_tag_number() + 1 {
-			let mut edges = Vec::with_capacity(E::max_tag_number());
+/// The default number of posted events a Machine processes in one call to `on_event`
+pub const DEFAULT_RUN_LIMIT: usize = 1000;
 
-			for _ in 0..E::max_tag_number() + 1 {
-				edges.push(None);
-			}
+/// The EventQueue is a handle to a Machine's queue of posted events, actions can capture a clone
+/// of it to raise follow-up events on their own machine
+pub struct EventQueue<E> {
+	events: Shared<VecDeque<E>>,
+}
 
-			transitions.push(StateTransitions {
-				edges: edges,
-			});
+impl<E> EventQueue<E> {
+	fn new() -> EventQueue<E> {
+		EventQueue {
+			events: Shared::new(VecDeque::new()),
 		}
+	}
+
+	/// Posts an event to the back of the queue, it is processed once the machine has finished
+	/// handling the current event and any events posted before it
+	pub fn post(&self, event: E) {
+		self.events.with(|events| events.push_back(event));
+	}
+
+	/// Retrieves the number of events waiting in the queue
+	pub fn len(&self) -> usize {
+		self.events.with(|events| events.len())
+	}
+
+	/// Returns true if no events are waiting in the queue
+	pub fn is_empty(&self) -> bool {
+		self.events.with(|events| events.is_empty())
+	}
+
+	fn pop(&self) -> Option<E> {
+		self.events.with(|events| events.pop_front())
+	}
+}
 
-		Machine {
-			state: initial_state,
-			transitions: transitions,
+impl<E> Clone for EventQueue<E> {
+	fn clone(&self) -> EventQueue<E> {
+		EventQueue {
+			events: self.events.clone(),
 		}
 	}
+}
 
-	/// Registers a new valid transition with the FSM
-	pub fn add_transition<F>(&mut self, in_state: S, on_event: E, next_state: S, action: F) -> bool
-	where F: Fn(&S, &E) + 'a{
-		let transition = &mut self.transitions[in_state.tag_number()];
+/// The History of a state decides which of its substates is entered when a transition targets it
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum History {
+	/// Always enter the initial substate
+	None,
+	/// Resume the direct substate that was active when the state was last exited, entering that
+	/// substate's initial substates as usual
+	Shallow,
+	/// Resume the innermost substate that was active when the state was last exited
+	Deep,
+}
 
-		let edge = &mut transition.edges[on_event.tag_number()];
+/// The Closures decide how a StateMachine boxes its predicates, actions and clock, and so whether
+/// the machine can leave the thread it was built on. Machine uses the Local closures and
+/// SyncMachine the Sendable ones
+pub trait Closures<'a, S, E, C> {
+	/// the boxed action of a transition
+	type Action;
+	/// the boxed predicate of a guarded transition
+	type Predicate;
+	/// the boxed entry, exit or timed transition action of a state
+	type StateAction;
+	/// the clock timing how long the machine has been in each state
+	type Clock: ?Sized + Clock + 'a;
+}
 
-		if edge.is_none() {
-			*edge = Some(Transition {
-				action: Box::new(action),
-				next_state: next_state,
-			});
-			true
-		} else {
-			false
+/// The Blocking closures are those a StateMachine calls directly, rather than awaiting the futures
+/// they return, so its events are handled by `on_event` itself
+pub trait Blocking {}
+
+/// The Local closures may borrow from their surroundings and need not be `Send`, they are those
+/// of a Machine
+pub enum Local {}
+
+impl<'a, S, E, C> Closures<'a, S, E, C> for Local {
+	type Action = Action<'a, S, E, C>;
+	type Predicate = Predicate<'a, S, E, C>;
+	type StateAction = StateAction<'a, S, C>;
+	type Clock = dyn Clock + 'a;
+}
+
+impl Blocking for Local {}
+
+/// The Chart of a StateMachine, whose closures are boxed as its Closures `K` decide
+type MachineChart<'a, S, E, C, B, K> = Chart<
+	S,
+	<E as EventKind>::Kind,
+	B,
+	<K as Closures<'a, S, E, C>>::Action,
+	<K as Closures<'a, S, E, C>>::Predicate,
+	<K as Closures<'a, S, E, C>>::StateAction,
+>;
+
+/// The StateMachine is the Finite State Machine, which has a current state and set of all valid
+/// transitions, along with a context holding any extended state that predicates and actions use.
+/// How its predicates and actions are boxed is chosen by its Closures `K`, it is usually named
+/// through Machine or SyncMachine.
+///
+/// The transitions are found through one table, keyed by state and event, whose entries are the
+/// first of the Transitions for that state and event in a separate list. How that table and the
+/// records of each state are held is chosen by the Storage backend `B`, by default DenseStorage
+pub struct StateMachine<'a, S: EnumTag, E: EventKind, C = (), B: Storage = DenseStorage, K: Closures<'a, S, E, C> = Local> {
+	chart: MachineChart<'a, S, E, C, B, K>,
+	run: Run<S, E, C, B>,
+	clock: Option<Box<K::Clock>>,
+}
+
+/// The Machine is a StateMachine whose closures may borrow from their surroundings, so it can't
+/// leave the thread it was built on
+pub type Machine<'a, S, E, C = (), B = DenseStorage> = StateMachine<'a, S, E, C, B, Local>;
+
+/// Implements the constructors of a StateMachine whose closures are boxed by the given Closures
+macro_rules! machine_constructors {
+	($closures:ty) => {
+		impl<'a, S: EnumTag, E: EventKind> StateMachine<'a, S, E, (), DenseStorage, $closures> {
+			/// Constructs a new FSM with a given initial state
+			pub fn new(initial_state: S) -> StateMachine<'a, S, E, (), DenseStorage, $closures> {
+				Self::with_context(initial_state, ())
+			}
+		}
+
+		impl<'a, S: EnumTag, E: EventKind, C> StateMachine<'a, S, E, C, DenseStorage, $closures> {
+			/// Constructs a new FSM with a given initial state, owning the given context
+			pub fn with_context(initial_state: S, context: C) -> StateMachine<'a, S, E, C, DenseStorage, $closures> {
+				Self::with_storage(initial_state, context)
+			}
+		}
+
+		impl<'a, S: EnumTag, E: EventKind, C, B: Storage> StateMachine<'a, S, E, C, B, $closures> {
+			/// Constructs a new FSM with a given initial state, owning the given context, whose
+			/// transitions are held by the storage backend `B`
+			pub fn with_storage(initial_state: S, context: C) -> StateMachine<'a, S, E, C, B, $closures> {
+				#[cfg(feature = "std")]
+				let clock: Option<Box<<$closures as Closures<'a, S, E, C>>::Clock>> = Some(Box::new(SystemClock::new()));
+				#[cfg(not(feature = "std"))]
+				let clock: Option<Box<<$closures as Closures<'a, S, E, C>>::Clock>> = None;
+
+				StateMachine {
+					chart: Chart::new(initial_state),
+					run: Run::new(initial_state, context, Duration::ZERO),
+					clock,
+				}
+			}
+		}
+	};
+}
+
+#[cfg(feature = "async")]
+pub(crate) use machine_constructors;
+
+/// Implements the constructors and registration of a StateMachine whose closures are boxed by the
+/// given Closures, each closure having the given bounds
+macro_rules! machine_closures {
+	($closures:ty $(, $bound:path)*) => {
+		machine_constructors!($closures);
+
+		impl<'a, S: EnumTag, E: EventKind, C, B: Storage> StateMachine<'a, S, E, C, B, $closures> {
+			/// Registers a new valid transition with the FSM, returns false if the state already has an
+			/// unconditional transition for this event or the event's tag is beyond its `max_tag_number`
+			pub fn add_transition<F>(&mut self, in_state: S, on_event: E::Kind, next_state: S, mut action: F) -> bool
+			where F: FnMut(&S, &E) $(+ $bound)* + 'a {
+				self.add_transition_with_context(in_state, on_event, next_state, move |s, e, _| action(s, e))
+			}
+
+			/// Registers a new valid transition with the FSM whose action can modify the machine's context,
+			/// returns false if the state already has an unconditional transition for this event or the
+			/// event's tag is beyond its `max_tag_number`
+			pub fn add_transition_with_context<F>(&mut self, in_state: S, on_event: E::Kind, next_state: S, action: F) -> bool
+			where F: FnMut(&S, &E, &mut C) $(+ $bound)* + 'a {
+				self.chart.add_transition(in_state, on_event, next_state, None, Box::new(action))
+			}
+
+			/// Registers a new transition with the FSM that only occurs if the predicate returns true,
+			/// transitions for the same state and event are tried in the order they were added and the
+			/// first whose predicate passes is taken. Returns false if the state already has an
+			/// unconditional transition for this event, as the new transition could never be taken, or the
+			/// event's tag is beyond its `max_tag_number`
+			pub fn add_guarded_transition<P, F>(&mut self, in_state: S, on_event: E::Kind, next_state: S, predicate: P, mut action: F) -> bool
+			where P: Fn(&S, &E) -> bool $(+ $bound)* + 'a, F: FnMut(&S, &E) $(+ $bound)* + 'a {
+				self.add_guarded_transition_with_context(
+					in_state, on_event, next_state,
+					move |s, e, _| predicate(s, e), move |s, e, _| action(s, e)
+				)
+			}
+
+			/// Registers a new guarded transition with the FSM, as `add_guarded_transition`, whose
+			/// predicate can inspect the machine's context and whose action can modify it
+			pub fn add_guarded_transition_with_context<P, F>(&mut self, in_state: S, on_event: E::Kind, next_state: S, predicate: P, action: F) -> bool
+			where P: Fn(&S, &E, &C) -> bool $(+ $bound)* + 'a, F: FnMut(&S, &E, &mut C) $(+ $bound)* + 'a {
+				self.chart.add_transition(in_state, on_event, next_state, Some(Box::new(predicate)), Box::new(action))
+			}
+
+			/// Sets the action performed whenever the machine enters the given state, replacing any
+			/// previous entry action for that state
+			pub fn set_on_enter<F>(&mut self, state: S, mut action: F)
+			where F: FnMut(&S) $(+ $bound)* + 'a {
+				self.set_on_enter_with_context(state, move |s, _| action(s));
+			}
+
+			/// Sets the action performed whenever the machine enters the given state, as `set_on_enter`,
+			/// which can modify the machine's context
+			pub fn set_on_enter_with_context<F>(&mut self, state: S, action: F)
+			where F: FnMut(&S, &mut C) $(+ $bound)* + 'a {
+				self.chart.record_mut(state).on_enter = Some(Box::new(action));
+			}
+
+			/// Sets the action performed whenever the machine exits the given state, replacing any
+			/// previous exit action for that state
+			pub fn set_on_exit<F>(&mut self, state: S, mut action: F)
+			where F: FnMut(&S) $(+ $bound)* + 'a {
+				self.set_on_exit_with_context(state, move |s, _| action(s));
+			}
+
+			/// Sets the action performed whenever the machine exits the given state, as `set_on_exit`,
+			/// which can modify the machine's context
+			pub fn set_on_exit_with_context<F>(&mut self, state: S, action: F)
+			where F: FnMut(&S, &mut C) $(+ $bound)* + 'a {
+				self.chart.record_mut(state).on_exit = Some(Box::new(action));
+			}
+
+			/// Registers a transition that the machine takes once it has been in the given state, or any of
+			/// its substates, for the given time. Returns false if the state already has a timed transition,
+			/// or if the machine has no clock to time it, which without the `std` feature must first be
+			/// given with `set_clock`
+			pub fn add_timed_transition<F>(&mut self, in_state: S, after: Duration, next_state: S, mut action: F) -> bool
+			where F: FnMut(&S) $(+ $bound)* + 'a {
+				self.add_timed_transition_with_context(in_state, after, next_state, move |s, _| action(s))
+			}
+
+			/// Registers a timed transition, as `add_timed_transition`, whose action can modify the
+			/// machine's context
+			pub fn add_timed_transition_with_context<F>(&mut self, in_state: S, after: Duration, next_state: S, action: F) -> bool
+			where F: FnMut(&S, &mut C) $(+ $bound)* + 'a {
+				self.clock.is_some() && self.chart.add_timed_transition(in_state, after, next_state, Box::new(action))
+			}
+
+			/// Replaces the clock used to time how long the machine has been in each state, the current
+			/// state and its ancestors are treated as having just been entered. Machines use a
+			/// SystemClock by default, while without the `std` feature they have no clock until one is set
+			pub fn set_clock<T>(&mut self, clock: T)
+			where T: Clock $(+ $bound)* + 'a {
+				self.run.set_clock(clock.now());
+				self.clock = Some(Box::new(clock));
+			}
 		}
+	};
+}
+
+machine_closures!(Local);
+machine_closures!(Sendable, Send, Sync);
+
+impl<'a, S: EnumTag, E: EventKind, C, B: Storage, K: Closures<'a, S, E, C>> StateMachine<'a, S, E, C, B, K> {
+	/// Makes the given state a substate of the parent, so events the state has no transition for
+	/// are offered to the parent. Returns false if the parent is the state itself or one of its
+	/// substates, as the hierarchy would contain a cycle
+	pub fn set_parent(&mut self, state: S, parent: S) -> bool {
+		self.chart.set_parent(state, parent)
+	}
+
+	/// Sets the substate that is entered whenever a transition targets the parent state, returns
+	/// false if the substate's parent is not the given parent
+	pub fn set_initial_substate(&mut self, parent: S, substate: S) -> bool {
+		self.chart.set_initial_substate(parent, substate)
+	}
+
+	/// Retrieves the time, as measured by the machine's clock, at which the earliest timed
+	/// transition of the current state or its ancestors is due
+	pub fn next_deadline(&self) -> Option<Duration> {
+		self.run.next_timeout(&self.chart, self.clock.as_deref()).map(|(_, deadline)| deadline)
+	}
+
+	/// Defers events of the given kind while the machine is in the given state, or any of its
+	/// substates, instead of dropping them when there is no transition for them. Deferred events
+	/// are replayed, ahead of any posted events, after the next transition
+	pub fn defer_event(&mut self, state: S, on_event: E::Kind) {
+		self.chart.defer_event(state, on_event);
+	}
+
+	/// Retrieves the number of deferred events waiting to be replayed
+	pub fn deferred_events(&self) -> usize {
+		self.run.deferred_events()
+	}
+
+	/// Sets whether the given state resumes its previously active substate when it is re-entered,
+	/// rather than its initial substate
+	pub fn set_history(&mut self, state: S, history: History) {
+		self.chart.record_mut(state).history = history;
+	}
+
+	/// Forgets which substate of the given state was last active, so it is next entered through
+	/// its initial substate
+	pub fn clear_history(&mut self, state: S) {
+		self.run.clear_history(state);
+	}
+
+	/// Marks the given state as final, so that `validate` expects it to have no way out
+	pub fn mark_final(&mut self, state: S) {
+		self.chart.record_mut(state).is_final = true;
+	}
+
+	/// Returns true if the given state has been marked as final
+	pub fn is_final(&self, state: S) -> bool {
+		self.chart.is_final(state)
+	}
+
+	/// Retrieves the parent of the given state, if it has one
+	pub fn parent(&self, state: S) -> Option<S> {
+		self.chart.parent(state)
+	}
+
+	/// Returns true if the given state is the current state or one of its ancestors
+	pub fn is_in(&self, state: S) -> bool {
+		self.chart.is_descendant_or_self(self.run.state, state)
 	}
 
 	/// Retrieves a reference to the current state
 	pub fn current_state(&self) -> S {
-		self.state
+		self.run.state
 	}
 
-	/// Tick the State Machine with an Event
-	pub fn on_event(&mut self, event_type: E) {
-		let transition = &self.transitions[self.state.tag_number()];
-		let edge = &transition.edges[event_type.tag_number()];
-		if let &Some(ref t) = edge {
-			(*t.action)(&self.state, &event_type);
-			self.state = t.next_state;
-		}
+	/// Retrieves the state the machine was constructed in
+	pub fn initial_state(&self) -> S {
+		self.chart.initial_state
+	}
+
+	/// Retrieves a reference to the machine's context
+	pub fn context(&self) -> &C {
+		&self.run.context
+	}
+
+	/// Retrieves a mutable reference to the machine's context
+	pub fn context_mut(&mut self) -> &mut C {
+		&mut self.run.context
+	}
+
+	/// Takes a snapshot of the machine's current state and context, which can be restored into a
+	/// machine with the same transitions later. A machine that has not started yet is recorded in
+	/// the state it will start in
+	pub fn snapshot(&self) -> Snapshot<S, C>
+	where C: Clone {
+		self.run.snapshot(&self.chart)
+	}
+
+	/// Restores the current state and context from a snapshot, without running any entry actions.
+	/// The current state and its ancestors are treated as having just been entered, while history,
+	/// posted and deferred events are left as they are. The snapshot is rejected, leaving the
+	/// machine unchanged, if its state is not part of this machine's transitions or is a state the
+	/// machine could never be left in
+	pub fn restore(&mut self, snapshot: Snapshot<S, C>) -> Result<(), RestoreError<S>> {
+		self.run.restore(&self.chart, snapshot, self.clock.as_ref().map(|c| c.now()))
+	}
+
+	/// Retrieves a handle to the machine's event queue, which actions can use to post events to
+	/// the machine while it is handling another. The queue is only made once it is first asked for,
+	/// until then handling an event doesn't check it for posted events
+	pub fn event_queue(&mut self) -> EventQueue<E> {
+		self.run.event_queue()
+	}
+
+	/// Retrieves the number of posted events waiting to be processed
+	pub fn pending_events(&self) -> usize {
+		self.run.pending_events()
+	}
+
+	/// Sets the maximum number of posted events processed by one call to `on_event` or
+	/// `process_pending`, guarding against actions that keep posting events to each other forever.
+	/// Defaults to `DEFAULT_RUN_LIMIT`
+	pub fn set_run_limit(&mut self, run_limit: usize) {
+		self.run.set_run_limit(run_limit);
+	}
+}
+
+impl<'a, S: EnumTag, E: EventKind, C, B: Storage, K: Closures<'a, S, E, C> + Blocking> StateMachine<'a, S, E, C, B, K>
+where
+	K::Action: FnMut(&S, &E, &mut C),
+	K::Predicate: Fn(&S, &E, &C) -> bool,
+	K::StateAction: FnMut(&S, &mut C),
+{
+	/// Tick the State Machine with an Event, reporting whether it triggered a transition. The
+	/// transition is chosen by the event's kind and the event itself is passed to its predicates
+	/// and action. If the current state has no transition for the event it is offered to each of
+	/// its ancestors in turn.
+	///
+	/// When a transition is taken the states being left are exited from the current state up to
+	/// (but not including) the common ancestor of the current and next states, then the
+	/// transition's action runs, then the states being entered are entered from below the common
+	/// ancestor down to the next state and on through its initial (or, with history, previously
+	/// active) substates. Transitions back into the same state, or one of its ancestors, also exit
+	/// and re-enter it.
+	///
+	/// Each event runs to completion before the next is processed: once this event is handled any
+	/// deferred events being replayed and events posted to the event queue are processed in order,
+	/// up to the run limit. Events left
+	/// over when the limit is reached stay queued, see `pending_events` and `process_pending`
+	pub fn on_event(&mut self, event_type: E) -> TransitionOutcome<S> {
+		self.run.on_event(&mut self.chart, self.clock.as_deref(), event_type)
+	}
+
+	/// Enters the initial substates of the state the machine was constructed in, running their
+	/// entry actions, so that the machine rests in a leaf state. Until then `current_state` is the
+	/// state it was constructed in. Machines start themselves when they first handle an event or
+	/// advance their clock, so this is only needed to run those entry actions sooner. Does nothing
+	/// once the machine has started
+	pub fn start(&mut self) {
+		self.run.start(&mut self.chart, self.clock.as_deref());
+	}
+
+	/// Processes events waiting in the event queue, up to the run limit, returning how many were
+	/// processed
+	pub fn process_pending(&mut self) -> usize {
+		self.run.process_pending(&mut self.chart, self.clock.as_deref())
+	}
+
+	/// Takes every timed transition that is due at the given time, as measured by the machine's
+	/// clock, reporting the transitions taken. States entered by a timed transition are treated
+	/// as having been entered when it was due, so chains of timeouts catch up with the clock.
+	/// Events posted by the transitions' actions are processed as for `on_event`, and at most the
+	/// run limit of timed transitions are taken
+	pub fn advance(&mut self, now: Duration) -> Vec<TransitionOutcome<S>> {
+		self.run.advance(&mut self.chart, self.clock.as_deref(), now)
 	}
 }
 
@@ -123,6 +620,111 @@ mod test {
 		}
 	}
 
+	#[derive(Debug, Eq, PartialEq)]
+	enum TurnStyleInput {
+		Push,
+		Coin(Vec<u32>),
+	}
+
+	impl EventKind for TurnStyleInput {
+		type Kind = TurnStyleEvent;
+
+		fn kind(&self) -> TurnStyleEvent {
+			match *self {
+				TurnStyleInput::Push => TurnStyleEvent::Push,
+				TurnStyleInput::Coin(_) => TurnStyleEvent::InsertCoin,
+			}
+		}
+	}
+
+	#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+	enum EditorState {
+		Idle,
+		Active,
+		Editing,
+		Saving,
+		Failed,
+	}
+
+	#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+	enum EditorEvent {
+		Open,
+		Save,
+		Saved,
+		Cancel,
+		Fail,
+	}
+
+	impl EnumTag for EditorState {
+		fn tag_number(&self) -> usize {
+			*self as usize
+		}
+		fn max_tag_number() -> usize {
+			EditorState::Failed as usize
+		}
+	}
+
+	impl EnumTag for EditorEvent {
+		fn tag_number(&self) -> usize {
+			*self as usize
+		}
+		fn max_tag_number() -> usize {
+			EditorEvent::Fail as usize
+		}
+	}
+
+	#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+	enum PlayerState {
+		Off,
+		On,
+		Menu,
+		Playback,
+		Playing,
+		Paused,
+	}
+
+	#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+	enum PlayerEvent {
+		Power,
+		Select,
+		Pause,
+	}
+
+	impl EnumTag for PlayerState {
+		fn tag_number(&self) -> usize {
+			*self as usize
+		}
+		fn max_tag_number() -> usize {
+			PlayerState::Paused as usize
+		}
+	}
+
+	impl EnumTag for PlayerEvent {
+		fn tag_number(&self) -> usize {
+			*self as usize
+		}
+		fn max_tag_number() -> usize {
+			PlayerEvent::Pause as usize
+		}
+	}
+
+	fn player<'a>() -> Machine<'a, PlayerState, PlayerEvent> {
+		let mut machine = Machine::new(PlayerState::Off);
+		machine.set_parent(PlayerState::Menu, PlayerState::On);
+		machine.set_parent(PlayerState::Playback, PlayerState::On);
+		machine.set_parent(PlayerState::Playing, PlayerState::Playback);
+		machine.set_parent(PlayerState::Paused, PlayerState::Playback);
+		machine.set_initial_substate(PlayerState::On, PlayerState::Menu);
+		machine.set_initial_substate(PlayerState::Playback, PlayerState::Playing);
+		machine.add_transition(PlayerState::Off, PlayerEvent::Power, PlayerState::On, |_,_| {});
+		machine.add_transition(PlayerState::On, PlayerEvent::Power, PlayerState::Off, |_,_| {});
+		machine.add_transition(PlayerState::Menu, PlayerEvent::Select, PlayerState::Playback, |_,_| {});
+		machine.add_transition(PlayerState::Playback, PlayerEvent::Select, PlayerState::Menu, |_,_| {});
+		machine.add_transition(PlayerState::Playing, PlayerEvent::Pause, PlayerState::Paused, |_,_| {});
+		machine.add_transition(PlayerState::Paused, PlayerEvent::Pause, PlayerState::Playing, |_,_| {});
+		machine
+	}
+
 	#[test]
 	fn test_machine() {
 		let mut machine = Machine::new(TurnStyleState::Locked);
@@ -144,4 +746,487 @@ mod test {
 		machine.on_event(TurnStyleEvent::Push);
 		assert!(machine.current_state() == TurnStyleState::Locked);
 	}
+
+	#[test]
+	fn test_guarded_transitions() {
+		use std::cell::Cell;
+
+		let coins = Cell::new(0);
+		let mut machine = Machine::new(TurnStyleState::Locked);
+		assert!(machine.add_guarded_transition(
+			TurnStyleState::Locked, TurnStyleEvent::InsertCoin,
+			TurnStyleState::Unlocked, |_,_| coins.get() >= 2, |_,_| coins.set(0)
+		));
+		assert!(machine.add_transition(
+			TurnStyleState::Locked, TurnStyleEvent::InsertCoin,
+			TurnStyleState::Locked, |_,_| coins.set(coins.get() + 1)
+		));
+		assert!(!machine.add_guarded_transition(
+			TurnStyleState::Locked, TurnStyleEvent::InsertCoin,
+			TurnStyleState::Unlocked, |_,_| true, |_,_| {}
+		));
+		machine.on_event(TurnStyleEvent::InsertCoin);
+		assert!(machine.current_state() == TurnStyleState::Locked);
+		machine.on_event(TurnStyleEvent::InsertCoin);
+		assert!(machine.current_state() == TurnStyleState::Locked);
+		machine.on_event(TurnStyleEvent::InsertCoin);
+		assert!(machine.current_state() == TurnStyleState::Unlocked);
+		assert_eq!(coins.get(), 0);
+	}
+
+	#[test]
+	fn test_guarded_transitions_tried_in_order() {
+		let mut machine = Machine::new(TurnStyleState::Locked);
+		machine.add_guarded_transition(
+			TurnStyleState::Locked, TurnStyleEvent::Push,
+			TurnStyleState::Unlocked, |_,_| false, |_,_| panic!("guard should have failed")
+		);
+		machine.add_guarded_transition(
+			TurnStyleState::Locked, TurnStyleEvent::Push,
+			TurnStyleState::Unlocked, |_,_| true, |_,_| {}
+		);
+		machine.add_guarded_transition(
+			TurnStyleState::Locked, TurnStyleEvent::Push,
+			TurnStyleState::Locked, |_,_| true, |_,_| panic!("earlier transition should win")
+		);
+		machine.on_event(TurnStyleEvent::Push);
+		assert!(machine.current_state() == TurnStyleState::Unlocked);
+		machine.add_guarded_transition(
+			TurnStyleState::Unlocked, TurnStyleEvent::Push,
+			TurnStyleState::Locked, |_,_| false, |_,_| {}
+		);
+		machine.on_event(TurnStyleEvent::Push);
+		assert!(machine.current_state() == TurnStyleState::Unlocked);
+	}
+
+	#[test]
+	fn test_transition_outcome() {
+		let mut machine = Machine::new(TurnStyleState::Locked);
+		machine.add_transition(
+			TurnStyleState::Locked, TurnStyleEvent::InsertCoin,
+			TurnStyleState::Unlocked, |_,_| {}
+		);
+		machine.add_guarded_transition(
+			TurnStyleState::Unlocked, TurnStyleEvent::Push,
+			TurnStyleState::Locked, |_,_| false, |_,_| {}
+		);
+		let outcome = machine.on_event(TurnStyleEvent::Push);
+		assert_eq!(outcome, TransitionOutcome::Unhandled { state: TurnStyleState::Locked });
+		assert!(!outcome.is_handled());
+		let outcome = machine.on_event(TurnStyleEvent::InsertCoin);
+		assert_eq!(outcome, TransitionOutcome::Handled {
+			previous: TurnStyleState::Locked,
+			current: TurnStyleState::Unlocked,
+		});
+		assert!(outcome.is_handled());
+		let outcome = machine.on_event(TurnStyleEvent::Push);
+		assert_eq!(outcome, TransitionOutcome::Unhandled { state: TurnStyleState::Unlocked });
+	}
+
+	/// An event type whose last variant is beyond what its `max_tag_number` admits
+	#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+	enum ShortEvent {
+		Push,
+		InsertCoin,
+		Kick,
+	}
+
+	impl EnumTag for ShortEvent {
+		fn tag_number(&self) -> usize {
+			*self as usize
+		}
+		fn max_tag_number() -> usize {
+			ShortEvent::InsertCoin as usize
+		}
+	}
+
+	#[test]
+	fn test_event_out_of_range_rejected() {
+		let mut machine = Machine::new(TurnStyleState::Locked);
+		assert!(!machine.add_transition(TurnStyleState::Locked, ShortEvent::Kick, TurnStyleState::Unlocked, |_,_| {}));
+		assert!(machine.add_transition(TurnStyleState::Locked, ShortEvent::InsertCoin, TurnStyleState::Unlocked, |_,_| {}));
+		assert!(machine.add_transition(TurnStyleState::Unlocked, ShortEvent::Push, TurnStyleState::Locked, |_,_| {}));
+		machine.on_event(ShortEvent::InsertCoin);
+		assert_eq!(machine.on_event(ShortEvent::Push), TransitionOutcome::Handled {
+			previous: TurnStyleState::Unlocked,
+			current: TurnStyleState::Locked,
+		});
+	}
+
+	#[test]
+	fn test_event_out_of_range_unhandled() {
+		let mut machine = Machine::new(TurnStyleState::Locked);
+		machine.add_transition(TurnStyleState::Unlocked, ShortEvent::Push, TurnStyleState::Locked, |_,_| {});
+		// Kick's slot in Locked would otherwise be Unlocked's Push slot
+		assert_eq!(machine.on_event(ShortEvent::Kick), TransitionOutcome::Unhandled { state: TurnStyleState::Locked });
+	}
+
+	#[test]
+	fn test_entry_and_exit_actions() {
+		use std::cell::RefCell;
+
+		let log = RefCell::new(Vec::new());
+		let mut machine = Machine::new(TurnStyleState::Locked);
+		machine.add_transition(
+			TurnStyleState::Locked, TurnStyleEvent::InsertCoin,
+			TurnStyleState::Unlocked, |_,_| log.borrow_mut().push("unlock")
+		);
+		machine.add_transition(
+			TurnStyleState::Unlocked, TurnStyleEvent::InsertCoin,
+			TurnStyleState::Unlocked, |_,_| log.borrow_mut().push("refund")
+		);
+		machine.set_on_exit(TurnStyleState::Locked, |_| log.borrow_mut().push("exit locked"));
+		machine.set_on_enter(TurnStyleState::Unlocked, |_| log.borrow_mut().push("enter unlocked"));
+		machine.set_on_exit(TurnStyleState::Unlocked, |_| log.borrow_mut().push("exit unlocked"));
+		machine.on_event(TurnStyleEvent::Push);
+		assert!(log.borrow().is_empty());
+		machine.on_event(TurnStyleEvent::InsertCoin);
+		assert_eq!(*log.borrow(), ["exit locked", "unlock", "enter unlocked"]);
+		log.borrow_mut().clear();
+		machine.on_event(TurnStyleEvent::InsertCoin);
+		assert_eq!(*log.borrow(), ["exit unlocked", "refund", "enter unlocked"]);
+	}
+
+	#[test]
+	fn test_events_with_data() {
+		use std::cell::Cell;
+
+		let total = Cell::new(0);
+		let mut machine = Machine::new(TurnStyleState::Locked);
+		machine.add_guarded_transition(
+			TurnStyleState::Locked, TurnStyleEvent::InsertCoin, TurnStyleState::Unlocked,
+			|_, input| match *input {
+				TurnStyleInput::Coin(ref coins) => coins.iter().sum::<u32>() >= 50,
+				TurnStyleInput::Push => false,
+			},
+			|_, input| if let TurnStyleInput::Coin(ref coins) = *input {
+				total.set(total.get() + coins.iter().sum::<u32>());
+			}
+		);
+		machine.add_transition(
+			TurnStyleState::Unlocked, TurnStyleEvent::Push,
+			TurnStyleState::Locked, |_, input| assert_eq!(*input, TurnStyleInput::Push)
+		);
+		assert!(!machine.on_event(TurnStyleInput::Coin(vec![10, 20])).is_handled());
+		assert!(machine.on_event(TurnStyleInput::Coin(vec![20, 20, 10])).is_handled());
+		assert_eq!(machine.current_state(), TurnStyleState::Unlocked);
+		assert_eq!(total.get(), 50);
+		assert!(machine.on_event(TurnStyleInput::Push).is_handled());
+		assert_eq!(machine.current_state(), TurnStyleState::Locked);
+	}
+
+	#[test]
+	fn test_context() {
+		struct Counters {
+			coins: u32,
+			passes: u32,
+			entries: u32,
+		}
+
+		let mut machine = Machine::with_context(TurnStyleState::Locked, Counters {
+			coins: 0,
+			passes: 0,
+			entries: 0,
+		});
+		machine.add_guarded_transition_with_context(
+			TurnStyleState::Locked, TurnStyleEvent::InsertCoin, TurnStyleState::Unlocked,
+			|_, _, counters| counters.coins >= 1, |_, _, counters| counters.coins = 0
+		);
+		machine.add_transition_with_context(
+			TurnStyleState::Locked, TurnStyleEvent::InsertCoin,
+			TurnStyleState::Locked, |_, _, counters| counters.coins += 1
+		);
+		machine.add_transition_with_context(
+			TurnStyleState::Unlocked, TurnStyleEvent::Push,
+			TurnStyleState::Locked, |_, _, counters| counters.passes += 1
+		);
+		machine.set_on_enter_with_context(TurnStyleState::Locked, |_, counters| counters.entries += 1);
+		machine.on_event(TurnStyleEvent::InsertCoin);
+		assert_eq!(machine.current_state(), TurnStyleState::Locked);
+		assert_eq!(machine.context().coins, 1);
+		machine.on_event(TurnStyleEvent::InsertCoin);
+		assert_eq!(machine.current_state(), TurnStyleState::Unlocked);
+		assert_eq!(machine.context().coins, 0);
+		machine.on_event(TurnStyleEvent::Push);
+		assert_eq!(machine.context().passes, 1);
+		assert_eq!(machine.context().entries, 2);
+		machine.context_mut().coins = 5;
+		machine.on_event(TurnStyleEvent::InsertCoin);
+		assert_eq!(machine.current_state(), TurnStyleState::Unlocked);
+	}
+
+	#[test]
+	fn test_mutable_actions() {
+		let mut coins = 0;
+		let mut passes = Vec::new();
+		{
+			let mut machine = Machine::new(TurnStyleState::Locked);
+			machine.add_transition(
+				TurnStyleState::Locked, TurnStyleEvent::InsertCoin,
+				TurnStyleState::Unlocked, |_,_| coins += 1
+			);
+			machine.add_transition(
+				TurnStyleState::Unlocked, TurnStyleEvent::Push,
+				TurnStyleState::Locked, |_,_| passes.push(passes.len())
+			);
+			let mut entries = 0;
+			machine.set_on_enter(TurnStyleState::Unlocked, move |_| {
+				entries += 1;
+				assert!(entries <= 2);
+			});
+			for _ in 0..2 {
+				machine.on_event(TurnStyleEvent::InsertCoin);
+				machine.on_event(TurnStyleEvent::Push);
+			}
+		}
+		assert_eq!(coins, 2);
+		assert_eq!(passes, [0, 1]);
+	}
+
+	#[test]
+	fn test_hierarchical_states() {
+		use std::cell::RefCell;
+
+		let log = RefCell::new(Vec::new());
+		let mut machine = Machine::new(EditorState::Idle);
+		assert!(machine.set_parent(EditorState::Editing, EditorState::Active));
+		assert!(machine.set_parent(EditorState::Saving, EditorState::Active));
+		assert!(!machine.set_parent(EditorState::Active, EditorState::Saving));
+		assert!(!machine.set_initial_substate(EditorState::Active, EditorState::Idle));
+		assert!(machine.set_initial_substate(EditorState::Active, EditorState::Editing));
+		for &state in &[EditorState::Idle, EditorState::Active, EditorState::Editing, EditorState::Saving] {
+			let log = &log;
+			machine.set_on_enter(state, move |s| log.borrow_mut().push(format!("enter {:?}", s)));
+			machine.set_on_exit(state, move |s| log.borrow_mut().push(format!("exit {:?}", s)));
+		}
+		machine.add_transition(EditorState::Idle, EditorEvent::Open, EditorState::Active, |_,_| log.borrow_mut().push("open".to_string()));
+		machine.add_transition(EditorState::Editing, EditorEvent::Save, EditorState::Saving, |_,_| {});
+		machine.add_transition(EditorState::Saving, EditorEvent::Saved, EditorState::Editing, |_,_| {});
+		machine.add_transition(EditorState::Active, EditorEvent::Cancel, EditorState::Idle, |_,_| log.borrow_mut().push("cancel".to_string()));
+		machine.add_transition(EditorState::Active, EditorEvent::Fail, EditorState::Failed, |_,_| {});
+		machine.add_transition(EditorState::Active, EditorEvent::Open, EditorState::Active, |_,_| {});
+		machine.add_transition(EditorState::Saving, EditorEvent::Fail, EditorState::Editing, |_,_| {});
+
+		assert_eq!(machine.on_event(EditorEvent::Open), TransitionOutcome::Handled {
+			previous: EditorState::Idle,
+			current: EditorState::Editing,
+		});
+		assert_eq!(*log.borrow(), ["exit Idle", "open", "enter Active", "enter Editing"]);
+		assert!(machine.is_in(EditorState::Active));
+		assert!(!machine.is_in(EditorState::Saving));
+
+		log.borrow_mut().clear();
+		machine.on_event(EditorEvent::Save);
+		assert_eq!(*log.borrow(), ["exit Editing", "enter Saving"]);
+
+		// the leaf's own transition takes priority over its parent's
+		log.borrow_mut().clear();
+		machine.on_event(EditorEvent::Fail);
+		assert_eq!(machine.current_state(), EditorState::Editing);
+		assert_eq!(*log.borrow(), ["exit Saving", "enter Editing"]);
+
+		// transitions into an ancestor exit and re-enter it
+		log.borrow_mut().clear();
+		machine.on_event(EditorEvent::Open);
+		assert_eq!(*log.borrow(), ["exit Editing", "exit Active", "enter Active", "enter Editing"]);
+
+		// events the leaf doesn't handle bubble up to the parent
+		machine.on_event(EditorEvent::Save);
+		log.borrow_mut().clear();
+		assert_eq!(machine.on_event(EditorEvent::Cancel), TransitionOutcome::Handled {
+			previous: EditorState::Saving,
+			current: EditorState::Idle,
+		});
+		assert_eq!(*log.borrow(), ["exit Saving", "exit Active", "cancel", "enter Idle"]);
+		assert!(!machine.on_event(EditorEvent::Saved).is_handled());
+	}
+
+	#[test]
+	fn test_start_in_composite_state() {
+		use std::cell::RefCell;
+
+		let entered = RefCell::new(Vec::new());
+		let mut machine = Machine::new(PlayerState::On);
+		machine.set_parent(PlayerState::Menu, PlayerState::On);
+		machine.set_initial_substate(PlayerState::On, PlayerState::Menu);
+		machine.add_transition(PlayerState::Menu, PlayerEvent::Select, PlayerState::Off, |_,_| {});
+		machine.set_on_enter(PlayerState::Menu, |s| entered.borrow_mut().push(*s));
+		assert_eq!(machine.current_state(), PlayerState::On);
+		assert_eq!(machine.on_event(PlayerEvent::Select), TransitionOutcome::Handled {
+			previous: PlayerState::Menu,
+			current: PlayerState::Off,
+		});
+		assert_eq!(*entered.borrow(), [PlayerState::Menu]);
+
+		let mut machine: Machine<PlayerState, PlayerEvent> = Machine::new(PlayerState::On);
+		machine.set_parent(PlayerState::Menu, PlayerState::On);
+		machine.set_initial_substate(PlayerState::On, PlayerState::Menu);
+		machine.set_on_enter(PlayerState::Menu, |s| entered.borrow_mut().push(*s));
+		entered.borrow_mut().clear();
+		machine.start();
+		machine.start();
+		assert_eq!(machine.current_state(), PlayerState::Menu);
+		assert_eq!(*entered.borrow(), [PlayerState::Menu]);
+	}
+
+	#[test]
+	fn test_no_history() {
+		let mut machine = player();
+		for &event in &[PlayerEvent::Power, PlayerEvent::Select, PlayerEvent::Pause, PlayerEvent::Power] {
+			machine.on_event(event);
+		}
+		assert_eq!(machine.current_state(), PlayerState::Off);
+		machine.on_event(PlayerEvent::Power);
+		assert_eq!(machine.current_state(), PlayerState::Menu);
+	}
+
+	#[test]
+	fn test_shallow_history() {
+		let mut machine = player();
+		machine.set_history(PlayerState::On, History::Shallow);
+		for &event in &[PlayerEvent::Power, PlayerEvent::Select, PlayerEvent::Pause, PlayerEvent::Power] {
+			machine.on_event(event);
+		}
+		assert_eq!(machine.current_state(), PlayerState::Off);
+		machine.on_event(PlayerEvent::Power);
+		assert_eq!(machine.current_state(), PlayerState::Playing);
+		machine.on_event(PlayerEvent::Select);
+		machine.on_event(PlayerEvent::Power);
+		machine.on_event(PlayerEvent::Power);
+		assert_eq!(machine.current_state(), PlayerState::Menu);
+	}
+
+	#[test]
+	fn test_deep_history() {
+		use std::cell::RefCell;
+
+		let entered = RefCell::new(Vec::new());
+		let mut machine = player();
+		machine.set_history(PlayerState::On, History::Deep);
+		for &state in &[PlayerState::On, PlayerState::Menu, PlayerState::Playback, PlayerState::Playing, PlayerState::Paused] {
+			let entered = &entered;
+			machine.set_on_enter(state, move |s| entered.borrow_mut().push(*s));
+		}
+		for &event in &[PlayerEvent::Power, PlayerEvent::Select, PlayerEvent::Pause, PlayerEvent::Power] {
+			machine.on_event(event);
+		}
+		entered.borrow_mut().clear();
+		assert_eq!(machine.on_event(PlayerEvent::Power), TransitionOutcome::Handled {
+			previous: PlayerState::Off,
+			current: PlayerState::Paused,
+		});
+		assert_eq!(*entered.borrow(), [PlayerState::On, PlayerState::Playback, PlayerState::Paused]);
+
+		machine.on_event(PlayerEvent::Power);
+		machine.clear_history(PlayerState::On);
+		machine.on_event(PlayerEvent::Power);
+		assert_eq!(machine.current_state(), PlayerState::Menu);
+	}
+
+	#[test]
+	fn test_posted_events() {
+		use std::cell::RefCell;
+
+		let log = RefCell::new(Vec::new());
+		let log = &log;
+		let mut machine = Machine::new(EditorState::Idle);
+		let queue = machine.event_queue();
+		machine.add_transition(EditorState::Idle, EditorEvent::Open, EditorState::Editing, move |_,_| {
+			queue.post(EditorEvent::Save);
+			log.borrow_mut().push("open");
+		});
+		let queue = machine.event_queue();
+		machine.add_transition(EditorState::Editing, EditorEvent::Save, EditorState::Saving, move |_,_| {
+			queue.post(EditorEvent::Saved);
+			queue.post(EditorEvent::Cancel);
+			log.borrow_mut().push("save");
+		});
+		machine.add_transition(EditorState::Saving, EditorEvent::Saved, EditorState::Editing, |_,_| log.borrow_mut().push("saved"));
+		machine.add_transition(EditorState::Editing, EditorEvent::Cancel, EditorState::Idle, |_,_| log.borrow_mut().push("cancel"));
+		machine.set_on_enter(EditorState::Saving, |_| log.borrow_mut().push("enter saving"));
+
+		assert_eq!(machine.on_event(EditorEvent::Open), TransitionOutcome::Handled {
+			previous: EditorState::Idle,
+			current: EditorState::Editing,
+		});
+		assert_eq!(*log.borrow(), ["open", "save", "enter saving", "saved", "cancel"]);
+		assert_eq!(machine.current_state(), EditorState::Idle);
+		assert_eq!(machine.pending_events(), 0);
+	}
+
+	#[test]
+	fn test_run_limit() {
+		let mut machine = Machine::new(TurnStyleState::Locked);
+		let queue = machine.event_queue();
+		machine.add_transition(TurnStyleState::Locked, TurnStyleEvent::Push, TurnStyleState::Locked, move |_,_| queue.post(TurnStyleEvent::Push));
+		machine.set_run_limit(10);
+		assert!(machine.on_event(TurnStyleEvent::Push).is_handled());
+		assert_eq!(machine.pending_events(), 1);
+		assert_eq!(machine.process_pending(), 10);
+		assert_eq!(machine.pending_events(), 1);
+	}
+
+	#[test]
+	fn test_deferred_events() {
+		let mut machine = Machine::with_context(EditorState::Idle, Vec::new());
+		machine.set_parent(EditorState::Saving, EditorState::Active);
+		machine.defer_event(EditorState::Active, EditorEvent::Save);
+		machine.defer_event(EditorState::Active, EditorEvent::Cancel);
+		machine.add_transition(EditorState::Idle, EditorEvent::Save, EditorState::Saving, |_,_| {});
+		machine.add_transition_with_context(EditorState::Saving, EditorEvent::Saved, EditorState::Editing, |_,_,log| log.push("saved"));
+		machine.add_transition_with_context(EditorState::Editing, EditorEvent::Save, EditorState::Saving, |_,_,log| log.push("save"));
+		machine.add_transition_with_context(EditorState::Saving, EditorEvent::Cancel, EditorState::Idle, |_,_,log| log.push("cancel"));
+
+		machine.on_event(EditorEvent::Save);
+		assert_eq!(machine.on_event(EditorEvent::Save), TransitionOutcome::Deferred { state: EditorState::Saving });
+		assert_eq!(machine.on_event(EditorEvent::Fail), TransitionOutcome::Unhandled { state: EditorState::Saving });
+		assert_eq!(machine.deferred_events(), 1);
+		assert!(machine.on_event(EditorEvent::Saved).is_handled());
+		assert_eq!(machine.current_state(), EditorState::Saving);
+		assert_eq!(machine.deferred_events(), 0);
+		assert_eq!(*machine.context(), ["saved", "save"]);
+
+		// the state's own transition takes priority over deferring the event
+		machine.on_event(EditorEvent::Cancel);
+		assert_eq!(machine.current_state(), EditorState::Idle);
+	}
+
+	#[test]
+	fn test_timed_transitions() {
+		use std::time::Duration;
+
+		let clock = ManualClock::new();
+		let mut machine = Machine::with_context(PlayerState::Off, 0);
+		machine.set_clock(clock.clone());
+		machine.set_parent(PlayerState::Playing, PlayerState::Playback);
+		machine.set_parent(PlayerState::Paused, PlayerState::Playback);
+		machine.add_transition(PlayerState::Off, PlayerEvent::Power, PlayerState::Playing, |_,_| {});
+		machine.add_transition(PlayerState::Playing, PlayerEvent::Pause, PlayerState::Paused, |_,_| {});
+		machine.add_transition(PlayerState::Paused, PlayerEvent::Pause, PlayerState::Playing, |_,_| {});
+		assert!(machine.add_timed_transition_with_context(PlayerState::Paused, Duration::from_secs(5), PlayerState::Menu, |_, timeouts| *timeouts += 1));
+		assert!(!machine.add_timed_transition(PlayerState::Paused, Duration::from_secs(1), PlayerState::Off, |_| {}));
+		assert!(machine.add_timed_transition(PlayerState::Playback, Duration::from_secs(60), PlayerState::Off, |_| {}));
+		assert!(machine.add_timed_transition(PlayerState::Menu, Duration::from_secs(10), PlayerState::Off, |_| {}));
+		assert_eq!(machine.next_deadline(), None);
+
+		machine.on_event(PlayerEvent::Power);
+		assert_eq!(machine.next_deadline(), Some(Duration::from_secs(60)));
+		clock.advance(Duration::from_secs(50));
+		machine.on_event(PlayerEvent::Pause);
+		assert_eq!(machine.next_deadline(), Some(Duration::from_secs(55)));
+		assert!(machine.advance(clock.now()).is_empty());
+		clock.advance(Duration::from_secs(3));
+		machine.on_event(PlayerEvent::Pause);
+		machine.on_event(PlayerEvent::Pause);
+		assert_eq!(machine.next_deadline(), Some(Duration::from_secs(58)));
+
+		// the Paused timeout fires first, then Menu's timeout counts from when it was due
+		clock.advance(Duration::from_secs(20));
+		assert_eq!(machine.advance(clock.now()), [
+			TransitionOutcome::Handled { previous: PlayerState::Paused, current: PlayerState::Menu },
+			TransitionOutcome::Handled { previous: PlayerState::Menu, current: PlayerState::Off },
+		]);
+		assert_eq!(*machine.context(), 1);
+		assert_eq!(machine.next_deadline(), None);
+	}
 }
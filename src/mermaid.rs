@@ -0,0 +1,92 @@
+use alloc::string::String;
+use core::fmt::Write;
+
+use super::describe::StateInfo;
+use super::diagram::{state_descriptions, transition_label, DiagramOptions};
+use super::{Closures, EnumTag, EventKind, StateMachine, Storage};
+
+impl<'a, S: EnumTag, E: EventKind, C, B: Storage, K: Closures<'a, S, E, C>> StateMachine<'a, S, E, C, B, K> {
+	/// Draws the machine as a Mermaid `stateDiagram-v2`, substates being nested within their
+	/// parents and states with entry or exit actions being described as such. Guarded transitions
+	/// are marked and the initial state, and the initial substate of each parent, are pointed to
+	/// from the start marker
+	pub fn to_mermaid(&self, options: &DiagramOptions<S, E::Kind>) -> String {
+		let states = self.chart.state_infos();
+		let mut mermaid = String::new();
+		writeln!(mermaid, "---\ntitle: {}\n---", escape(&options.name)).unwrap();
+		writeln!(mermaid, "stateDiagram-v2").unwrap();
+		write_states(&mut mermaid, &states, None, 1, options);
+		writeln!(mermaid, "\t[*] --> s{}", self.chart.initial_state.tag_number()).unwrap();
+		for info in self.chart.transition_infos() {
+			let label = escape(&transition_label(&info, options));
+			writeln!(mermaid, "\ts{} --> s{} : {}", info.from.tag_number(), info.to.tag_number(), label).unwrap();
+		}
+		if options.highlight_current {
+			writeln!(mermaid, "\tclassDef current fill:lightgrey").unwrap();
+			writeln!(mermaid, "\tclass s{} current", self.run.state.tag_number()).unwrap();
+		}
+		mermaid
+	}
+}
+
+/// Declares the states with the given parent, and within those their own substates
+fn write_states<S: EnumTag, K>(mermaid: &mut String, states: &[StateInfo<S>], parent: Option<S>, depth: usize, options: &DiagramOptions<S, K>) {
+	let indent = "\t".repeat(depth);
+	for info in states.iter().filter(|info| info.parent.map(|p| p.tag_number()) == parent.map(|p| p.tag_number())) {
+		let tag = info.state.tag_number();
+		writeln!(mermaid, "{}state \"{}\" as s{}", indent, escape(&(options.state_label)(&info.state)), tag).unwrap();
+		for description in state_descriptions(info) {
+			writeln!(mermaid, "{}s{} : {}", indent, tag, description).unwrap();
+		}
+		if states.iter().any(|child| child.parent.map(|p| p.tag_number()) == Some(tag)) {
+			writeln!(mermaid, "{}state s{} {{", indent, tag).unwrap();
+			if let Some(initial) = info.initial {
+				writeln!(mermaid, "{}\t[*] --> s{}", indent, initial.tag_number()).unwrap();
+			}
+			write_states(mermaid, states, Some(info.state), depth + 1, options);
+			writeln!(mermaid, "{}}}", indent).unwrap();
+		}
+	}
+}
+
+/// Escapes text for Mermaid, which has no escape for quotes but accepts them as an entity
+fn escape(text: &str) -> String {
+	text.replace('"', "#quot;").replace('\n', " ")
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use super::super::diagram::test::{light, LightEvent};
+
+	#[test]
+	fn test_to_mermaid() {
+		let mut machine = light();
+		machine.on_event(LightEvent::Toggle);
+		assert_eq!(machine.to_mermaid(&DiagramOptions::debug().highlight_current(true)), "\
+---
+title: fsm
+---
+stateDiagram-v2
+	state \"Off\" as s0
+	state \"On\" as s1
+	s1 : on entry
+	s1 : on exit
+	state s1 {
+		[*] --> s3
+		state \"Dim\" as s3
+		state \"Bright\" as s4
+	}
+	state \"Broken\" as s2
+	[*] --> s0
+	s0 --> s1 : Toggle [guarded]
+	s0 --> s0 : Toggle
+	s1 --> s0 : Toggle
+	s1 --> s2 : after 3600s
+	s3 --> s4 : Dial
+	s4 --> s3 : Dial
+	classDef current fill:lightgrey
+	class s3 current
+");
+	}
+}
@@ -0,0 +1,193 @@
+use super::{Blocking, Closures, EnumTag, EventKind, StateMachine, Storage, TransitionOutcome};
+
+/// The Region is a machine that can be one of a ParallelMachine's regions
+pub trait Region {
+	/// the type of the events the region handles
+	type Event;
+	/// the type of the region's states
+	type State;
+	/// Tick the region with an Event, reporting whether it triggered a transition
+	fn on_event(&mut self, event: Self::Event) -> TransitionOutcome<Self::State>;
+	/// Retrieves the region's current state
+	fn current_state(&self) -> Self::State;
+}
+
+impl<'a, S: EnumTag, E: EventKind, C, B: Storage, K: Closures<'a, S, E, C> + Blocking> Region for StateMachine<'a, S, E, C, B, K>
+where
+	K::Action: FnMut(&S, &E, &mut C),
+	K::Predicate: Fn(&S, &E, &C) -> bool,
+	K::StateAction: FnMut(&S, &mut C),
+{
+	type Event = E;
+	type State = S;
+
+	fn on_event(&mut self, event: E) -> TransitionOutcome<S> {
+		self.on_event(event)
+	}
+
+	fn current_state(&self) -> S {
+		StateMachine::current_state(self)
+	}
+}
+
+/// The Regions are the regions of a ParallelMachine, a tuple of Regions that handle the same
+/// type of events but may each have their own states, context and storage
+pub trait Regions {
+	/// the type of the events every region handles
+	type Event;
+	/// the current state of each region, as a tuple
+	type States;
+	/// what each region did with an event, as a tuple
+	type Outcomes;
+	/// Tick every region with the Event, in order
+	fn on_event(&mut self, event: Self::Event) -> Self::Outcomes;
+	/// Retrieves the current state of every region
+	fn current_states(&self) -> Self::States;
+}
+
+macro_rules! regions {
+	($first:ident $first_index:tt $(, $region:ident $index:tt)+) => {
+		impl<$first: Region, $($region: Region<Event = $first::Event>),+> Regions for ($first, $($region),+)
+		where $first::Event: Clone {
+			type Event = $first::Event;
+			type States = ($first::State, $($region::State),+);
+			type Outcomes = (TransitionOutcome<$first::State>, $(TransitionOutcome<$region::State>),+);
+
+			fn on_event(&mut self, event: Self::Event) -> Self::Outcomes {
+				(self.$first_index.on_event(event.clone()), $(self.$index.on_event(event.clone())),+)
+			}
+
+			fn current_states(&self) -> Self::States {
+				(self.$first_index.current_state(), $(self.$index.current_state()),+)
+			}
+		}
+	};
+}
+
+regions!(A 0, B 1);
+regions!(A 0, B 1, C 2);
+regions!(A 0, B 1, C 2, D 3);
+regions!(A 0, B 1, C 2, D 3, F 4);
+regions!(A 0, B 1, C 2, D 3, F 4, G 5);
+
+/// The ParallelMachine holds several orthogonal regions, each of which is a machine with its own
+/// current state, all regions are active at once and every event is given to each of them. The
+/// regions are a tuple, so each can have its own types of states and context
+pub struct ParallelMachine<R> {
+	regions: R,
+}
+
+impl<R: Regions> ParallelMachine<R> {
+	/// Constructs a new parallel machine from a tuple of regions
+	pub fn new(regions: R) -> ParallelMachine<R> {
+		ParallelMachine {
+			regions,
+		}
+	}
+
+	/// Retrieves a reference to the regions
+	pub fn regions(&self) -> &R {
+		&self.regions
+	}
+
+	/// Retrieves a mutable reference to the regions
+	pub fn regions_mut(&mut self) -> &mut R {
+		&mut self.regions
+	}
+
+	/// Takes the regions back out of the machine
+	pub fn into_regions(self) -> R {
+		self.regions
+	}
+
+	/// Retrieves the current state of every region, in the order of the tuple
+	pub fn current_states(&self) -> R::States {
+		self.regions.current_states()
+	}
+
+	/// Tick every region with the Event, in the order of the tuple, reporting what each region did
+	/// with it
+	pub fn on_event(&mut self, event_type: R::Event) -> R::Outcomes {
+		self.regions.on_event(event_type)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use super::super::{Machine, SortedStorage};
+
+	#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+	enum ConnectionState {
+		Disconnected,
+		Connected,
+	}
+
+	#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+	enum AuthState {
+		Anonymous,
+		Authenticated,
+	}
+
+	#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+	enum SessionEvent {
+		Connect,
+		Login,
+		Drop,
+	}
+
+	impl EnumTag for ConnectionState {
+		fn tag_number(&self) -> usize {
+			*self as usize
+		}
+		fn max_tag_number() -> usize {
+			ConnectionState::Connected as usize
+		}
+	}
+
+	impl EnumTag for AuthState {
+		fn tag_number(&self) -> usize {
+			*self as usize
+		}
+		fn max_tag_number() -> usize {
+			AuthState::Authenticated as usize
+		}
+	}
+
+	impl EnumTag for SessionEvent {
+		fn tag_number(&self) -> usize {
+			*self as usize
+		}
+		fn max_tag_number() -> usize {
+			SessionEvent::Drop as usize
+		}
+	}
+
+	#[test]
+	fn test_parallel_regions() {
+		let mut connection = Machine::new(ConnectionState::Disconnected);
+		connection.add_transition(ConnectionState::Disconnected, SessionEvent::Connect, ConnectionState::Connected, |_,_| {});
+		connection.add_transition(ConnectionState::Connected, SessionEvent::Drop, ConnectionState::Disconnected, |_,_| {});
+		let mut authentication: Machine<_, _, u32, SortedStorage> = Machine::with_storage(AuthState::Anonymous, 0);
+		authentication.add_transition_with_context(AuthState::Anonymous, SessionEvent::Login, AuthState::Authenticated, |_, _, logins| *logins += 1);
+		authentication.add_transition(AuthState::Authenticated, SessionEvent::Drop, AuthState::Anonymous, |_,_| {});
+
+		let mut machine = ParallelMachine::new((connection, authentication));
+		assert_eq!(machine.current_states(), (ConnectionState::Disconnected, AuthState::Anonymous));
+
+		let outcomes = machine.on_event(SessionEvent::Connect);
+		assert_eq!(outcomes, (
+			TransitionOutcome::Handled { previous: ConnectionState::Disconnected, current: ConnectionState::Connected },
+			TransitionOutcome::Unhandled { state: AuthState::Anonymous },
+		));
+		machine.on_event(SessionEvent::Login);
+		assert_eq!(machine.current_states(), (ConnectionState::Connected, AuthState::Authenticated));
+		assert_eq!(*machine.regions().1.context(), 1);
+
+		let (connection, authentication) = machine.on_event(SessionEvent::Drop);
+		assert!(connection.is_handled() && authentication.is_handled());
+		assert_eq!(machine.current_states(), (ConnectionState::Disconnected, AuthState::Anonymous));
+		let (connection, _) = machine.into_regions();
+		assert!(connection.is_in(ConnectionState::Disconnected));
+	}
+}
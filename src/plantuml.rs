@@ -0,0 +1,83 @@
+use alloc::string::String;
+use core::fmt::Write;
+
+use super::describe::StateInfo;
+use super::diagram::{state_descriptions, transition_label, DiagramOptions};
+use super::{Closures, EnumTag, EventKind, StateMachine, Storage};
+
+impl<'a, S: EnumTag, E: EventKind, C, B: Storage, K: Closures<'a, S, E, C>> StateMachine<'a, S, E, C, B, K> {
+	/// Draws the machine as a PlantUML state diagram, laid out as `to_mermaid` does
+	pub fn to_plantuml(&self, options: &DiagramOptions<S, E::Kind>) -> String {
+		let states = self.chart.state_infos();
+		let mut plantuml = String::new();
+		writeln!(plantuml, "@startuml {}", escape(&options.name)).unwrap();
+		write_states(&mut plantuml, &states, None, 0, self.run.state.tag_number(), options);
+		writeln!(plantuml, "[*] --> s{}", self.chart.initial_state.tag_number()).unwrap();
+		for info in self.chart.transition_infos() {
+			let label = escape(&transition_label(&info, options));
+			writeln!(plantuml, "s{} --> s{} : {}", info.from.tag_number(), info.to.tag_number(), label).unwrap();
+		}
+		writeln!(plantuml, "@enduml").unwrap();
+		plantuml
+	}
+}
+
+/// Declares the states with the given parent, and within those their own substates
+fn write_states<S: EnumTag, K>(plantuml: &mut String, states: &[StateInfo<S>], parent: Option<S>, depth: usize, current: usize, options: &DiagramOptions<S, K>) {
+	let indent = "\t".repeat(depth);
+	for info in states.iter().filter(|info| info.parent.map(|p| p.tag_number()) == parent.map(|p| p.tag_number())) {
+		let tag = info.state.tag_number();
+		let colour = if options.highlight_current && tag == current { " #lightgrey" } else { "" };
+		let label = escape(&(options.state_label)(&info.state));
+		if states.iter().any(|child| child.parent.map(|p| p.tag_number()) == Some(tag)) {
+			writeln!(plantuml, "{}state \"{}\" as s{}{} {{", indent, label, tag, colour).unwrap();
+			if let Some(initial) = info.initial {
+				writeln!(plantuml, "{}\t[*] --> s{}", indent, initial.tag_number()).unwrap();
+			}
+			write_states(plantuml, states, Some(info.state), depth + 1, current, options);
+			writeln!(plantuml, "{}}}", indent).unwrap();
+		} else {
+			writeln!(plantuml, "{}state \"{}\" as s{}{}", indent, label, tag, colour).unwrap();
+		}
+		for description in state_descriptions(info) {
+			writeln!(plantuml, "{}s{} : {}", indent, tag, description).unwrap();
+		}
+	}
+}
+
+/// Escapes text for PlantUML, using its tilde escape for quotes
+fn escape(text: &str) -> String {
+	text.replace('~', "~~").replace('"', "~\"").replace('\n', " ")
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use super::super::diagram::test::{light, LightEvent};
+
+	#[test]
+	fn test_to_plantuml() {
+		let mut machine = light();
+		machine.on_event(LightEvent::Toggle);
+		assert_eq!(machine.to_plantuml(&DiagramOptions::debug().name("lamp").highlight_current(true)), "\
+@startuml lamp
+state \"Off\" as s0
+state \"On\" as s1 {
+	[*] --> s3
+	state \"Dim\" as s3 #lightgrey
+	state \"Bright\" as s4
+}
+s1 : on entry
+s1 : on exit
+state \"Broken\" as s2
+[*] --> s0
+s0 --> s1 : Toggle [guarded]
+s0 --> s0 : Toggle
+s1 --> s0 : Toggle
+s1 --> s2 : after 3600s
+s3 --> s4 : Dial
+s4 --> s3 : Dial
+@enduml
+");
+	}
+}
@@ -0,0 +1,757 @@
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use super::chart::Chart;
+use super::{
+	Clock, EnumTag, EventKind, EventQueue, History, RestoreError, Snapshot, Storage, TagMap,
+	TransitionOutcome, DEFAULT_RUN_LIMIT,
+};
+
+/// The Activity records when a state was last entered and, if it has history, which of its
+/// substates was last active
+struct Activity<S> {
+	entered_at: Duration,
+	last_active: Option<S>,
+}
+
+/// The Run is the running state of a machine: its current state and context, and once they are
+/// needed its posted and deferred events and the history and entry times of its states. It is
+/// driven through a Chart and timed by a clock that it doesn't own, so any number of Runs can
+/// share one Chart
+pub(crate) struct Run<S, E, C, B: Storage> {
+	pub(crate) state: S,
+	pub(crate) context: C,
+	started: bool,
+	extras: Option<Box<Extras<S, E, B>>>,
+}
+
+/// The Extras are the parts of a Run that many never use, kept apart so that until they are
+/// needed a Run is no more than its state and context
+struct Extras<S, E, B: Storage> {
+	/// the history of states that have it, and when timed states were entered
+	activity: B::Map<Activity<S>>,
+	/// the queue of posted events, made once a handle to it is first asked for
+	queue: Option<EventQueue<E>>,
+	deferred: VecDeque<E>,
+	/// deferred events waiting to be replayed, ahead of any posted events
+	replay: VecDeque<E>,
+	run_limit: usize,
+	/// when the run was created or its clock last set, states it has no record of entering are
+	/// treated as having been entered then
+	epoch: Duration,
+}
+
+impl<S, E, B: Storage> Extras<S, E, B> {
+	fn new(epoch: Duration) -> Extras<S, E, B> {
+		Extras {
+			activity: B::Map::new(),
+			queue: None,
+			deferred: VecDeque::new(),
+			replay: VecDeque::new(),
+			run_limit: DEFAULT_RUN_LIMIT,
+			epoch,
+		}
+	}
+}
+
+/// The Chart a Runner runs
+pub(crate) type RunnerChart<R, S, E, C, B> = Chart<
+	S,
+	<R as Runner<S, E, C, B>>::Kind,
+	B,
+	<R as Runner<S, E, C, B>>::Action,
+	<R as Runner<S, E, C, B>>::Predicate,
+	<R as Runner<S, E, C, B>>::StateAction,
+>;
+
+/// The Runner runs the predicates and actions of a Chart on behalf of a Run, so that the one
+/// driver below serves machines whose closures are boxed and called in different ways
+pub(crate) trait Runner<S, E, C, B: Storage> {
+	/// the kind of event the Chart's transitions are registered against
+	type Kind: EnumTag;
+	/// the Chart's transition actions
+	type Action;
+	/// the Chart's predicates
+	type Predicate;
+	/// the Chart's state actions
+	type StateAction;
+	/// Retrieves the Chart being run
+	fn chart(&self) -> &RunnerChart<Self, S, E, C, B>;
+	/// Finds the tag of the event's kind, if the Chart could have transitions for it
+	fn kind(&self, event: &E) -> Option<usize>;
+	/// Runs the predicate of the transition with the given index, if it has one
+	fn passes(&self, index: usize, state: &S, event: &E, context: &C) -> bool;
+	/// Runs the action of the transition with the given index
+	fn act(&mut self, index: usize, state: &S, event: &E, context: &mut C);
+	/// Runs the exit action of the state, if it has one
+	fn exit(&mut self, state: &S, context: &mut C);
+	/// Runs the entry action of the state, if it has one
+	fn enter(&mut self, state: &S, context: &mut C);
+	/// Runs the action of the timed state's timed transition, leaving the given state
+	fn time_out(&mut self, timed: S, state: &S, context: &mut C);
+}
+
+/// Finds the tag of an event's kind, rejecting tags beyond `max_tag_number`, which would
+/// otherwise land in the next state's row of a dense table
+pub(crate) fn kind_of<E: EventKind>(event: &E) -> Option<usize> {
+	let kind = event.kind().tag_number();
+	if kind <= E::Kind::max_tag_number() {
+		Some(kind)
+	} else {
+		None
+	}
+}
+
+impl<S, E, C, B, A, P, X> Runner<S, E, C, B> for Chart<S, E::Kind, B, A, P, X>
+where
+	S: EnumTag, E: EventKind, B: Storage,
+	A: FnMut(&S, &E, &mut C), P: Fn(&S, &E, &C) -> bool, X: FnMut(&S, &mut C),
+{
+	type Kind = E::Kind;
+	type Action = A;
+	type Predicate = P;
+	type StateAction = X;
+
+	fn chart(&self) -> &Chart<S, E::Kind, B, A, P, X> {
+		self
+	}
+
+	fn kind(&self, event: &E) -> Option<usize> {
+		kind_of(event)
+	}
+
+	fn passes(&self, index: usize, state: &S, event: &E, context: &C) -> bool {
+		match self.actions[index].predicate {
+			Some(ref predicate) => predicate(state, event, context),
+			None => true,
+		}
+	}
+
+	fn act(&mut self, index: usize, state: &S, event: &E, context: &mut C) {
+		(self.actions[index].action)(state, event, context);
+	}
+
+	fn exit(&mut self, state: &S, context: &mut C) {
+		if let Some(on_exit) = self.on_exit_mut(*state) {
+			on_exit(state, context);
+		}
+	}
+
+	fn enter(&mut self, state: &S, context: &mut C) {
+		if let Some(on_enter) = self.on_enter_mut(*state) {
+			on_enter(state, context);
+		}
+	}
+
+	fn time_out(&mut self, timed: S, state: &S, context: &mut C) {
+		if let Some(action) = self.timeout_action_mut(timed) {
+			action(state, context);
+		}
+	}
+}
+
+/// A shared Chart is run with closures that are `Fn`, so that many Runs can use it at once
+impl<S, E, C, B, A, P, X> Runner<S, E, C, B> for &Chart<S, E::Kind, B, A, P, X>
+where
+	S: EnumTag, E: EventKind, B: Storage,
+	A: Fn(&S, &E, &mut C), P: Fn(&S, &E, &C) -> bool, X: Fn(&S, &mut C),
+{
+	type Kind = E::Kind;
+	type Action = A;
+	type Predicate = P;
+	type StateAction = X;
+
+	fn chart(&self) -> &Chart<S, E::Kind, B, A, P, X> {
+		self
+	}
+
+	fn kind(&self, event: &E) -> Option<usize> {
+		kind_of(event)
+	}
+
+	fn passes(&self, index: usize, state: &S, event: &E, context: &C) -> bool {
+		match self.actions[index].predicate {
+			Some(ref predicate) => predicate(state, event, context),
+			None => true,
+		}
+	}
+
+	fn act(&mut self, index: usize, state: &S, event: &E, context: &mut C) {
+		(self.actions[index].action)(state, event, context);
+	}
+
+	fn exit(&mut self, state: &S, context: &mut C) {
+		if let Some(on_exit) = self.record(*state).and_then(|r| r.on_exit.as_ref()) {
+			on_exit(state, context);
+		}
+	}
+
+	fn enter(&mut self, state: &S, context: &mut C) {
+		if let Some(on_enter) = self.record(*state).and_then(|r| r.on_enter.as_ref()) {
+			on_enter(state, context);
+		}
+	}
+
+	fn time_out(&mut self, timed: S, state: &S, context: &mut C) {
+		if let Some(timeout) = self.record(timed).and_then(|r| r.timeout.as_ref()) {
+			(timeout.action)(state, context);
+		}
+	}
+}
+
+/// The Step is one part of a transition, whose action the driver runs
+enum Step<S> {
+	/// the state is exited
+	Exit(S),
+	/// the transition's own action runs, leaving the given state
+	Act(S),
+	/// the state is entered
+	Enter(S),
+}
+
+/// The Act is the action run for a transition's Step::Act
+#[derive(Copy, Clone)]
+enum Act<S> {
+	/// nothing, as the machine is only being started
+	Start,
+	/// the action of the transition with the given index, taken for the Drive's event
+	Transition(usize),
+	/// the action of the given timed state's timed transition
+	Timeout(S),
+}
+
+enum Phase<S> {
+	Exit(Option<S>),
+	Enter(Option<S>, S),
+	Substates(S),
+	Done,
+}
+
+/// The Transit steps through a transition from one state to another. The states being left are
+/// exited from the current state up to (but not including) the common ancestor of the current
+/// and next states, then the transition's action runs, then the states being entered are entered
+/// from below the common ancestor down to the next state and on through its initial (or, with
+/// history, previously active) substates. It records history and entry times in the Run as it
+/// goes and finally settles the Run in the innermost state entered
+struct Transit<S> {
+	previous: S,
+	ancestor: Option<S>,
+	next_state: S,
+	substate: Option<S>,
+	phase: Phase<S>,
+	/// when the states entered are treated as entered, if not now
+	at: Option<Duration>,
+}
+
+impl<S: EnumTag> Transit<S> {
+	fn new<K: EnumTag, B: Storage, A, P, X>(chart: &Chart<S, K, B, A, P, X>, previous: S, next_state: S) -> Transit<S> {
+		Transit {
+			previous,
+			ancestor: chart.common_ancestor(previous, next_state),
+			next_state,
+			substate: None,
+			phase: Phase::Exit(Some(previous)),
+			at: None,
+		}
+	}
+
+	/// Steps through the timed transition of the given timed state, due at the given deadline, so
+	/// that chains of timeouts catch up with the clock the states it enters are treated as entered
+	/// when it was due
+	fn timeout<K: EnumTag, B: Storage, A, P, X>(chart: &Chart<S, K, B, A, P, X>, previous: S, timed: S, deadline: Duration) -> Option<Transit<S>> {
+		let next_state = chart.record(timed)?.timeout.as_ref()?.next_state;
+		let mut transit = Transit::new(chart, previous, next_state);
+		transit.at = Some(deadline);
+		Some(transit)
+	}
+
+	/// Steps through nothing but entering the substates of the given state, for starting a machine
+	/// in it
+	fn start(state: S) -> Transit<S> {
+		Transit {
+			previous: state,
+			ancestor: None,
+			next_state: state,
+			substate: None,
+			phase: Phase::Substates(state),
+			at: None,
+		}
+	}
+
+	/// Finds the next step of the transition, or None once it is complete. Entering a state with a
+	/// timed transition records when it was entered, by the clock if there is one
+	fn next<E, C, K: EnumTag, B: Storage, A, P, X, T: ?Sized + Clock>(&mut self, chart: &Chart<S, K, B, A, P, X>, run: &mut Run<S, E, C, B>, clock: Option<&T>) -> Option<Step<S>> {
+		loop {
+			match self.phase {
+				Phase::Exit(Some(state)) if self.ancestor.map(|a| a.tag_number()) != Some(state.tag_number()) => {
+					if let Some(record) = chart.record(state) {
+						run.exited(state, record.history, self.substate, self.previous);
+					}
+					self.substate = Some(state);
+					self.phase = Phase::Exit(chart.parent(state));
+					return Some(Step::Exit(state));
+				},
+				Phase::Exit(_) => {
+					self.phase = Phase::Enter(self.ancestor, self.next_state);
+					return Some(Step::Act(self.previous));
+				},
+				Phase::Enter(entered, target) => {
+					if entered.map(|s| s.tag_number()) == Some(target.tag_number()) {
+						self.phase = Phase::Substates(target);
+						continue;
+					}
+					let state = chart.below(entered, target);
+					if chart.record(state).is_some_and(|r| r.timeout.is_some()) {
+						if let Some(at) = self.at.or_else(|| clock.map(T::now)) {
+							run.entered(state, at);
+						}
+					}
+					self.phase = Phase::Enter(Some(state), target);
+					return Some(Step::Enter(state));
+				},
+				Phase::Substates(state) => {
+					let (history, initial) = match chart.record(state) {
+						Some(record) => (record.history, record.initial),
+						None => (History::None, None),
+					};
+					self.phase = match (history, run.last_active(state), initial) {
+						(History::Deep, Some(innermost), _) => Phase::Enter(Some(state), innermost),
+						(History::Shallow, Some(substate), _) | (_, _, Some(substate)) => Phase::Enter(Some(state), substate),
+						(_, _, None) => {
+							run.settle(state);
+							Phase::Done
+						},
+					};
+				},
+				Phase::Done => return None,
+			}
+		}
+	}
+}
+
+impl<S: EnumTag, E, C, B: Storage> Run<S, E, C, B> {
+	/// Constructs a run in the given state, states it has no record of entering being treated as
+	/// entered at the epoch
+	pub(crate) fn new(state: S, context: C, epoch: Duration) -> Run<S, E, C, B> {
+		Run {
+			state,
+			context,
+			started: false,
+			extras: if epoch > Duration::ZERO { Some(Box::new(Extras::new(epoch))) } else { None },
+		}
+	}
+
+	fn extras_mut(&mut self) -> &mut Extras<S, E, B> {
+		self.extras.get_or_insert_with(|| Box::new(Extras::new(Duration::ZERO)))
+	}
+
+	/// Treats the current state and its ancestors as having just been entered, for a new clock
+	pub(crate) fn set_clock(&mut self, now: Duration) {
+		let extras = self.extras_mut();
+		extras.epoch = now;
+		for activity in extras.activity.values_mut() {
+			activity.entered_at = now;
+		}
+	}
+
+	fn activity_mut(&mut self, state: S) -> &mut Activity<S> {
+		let extras = self.extras_mut();
+		let epoch = extras.epoch;
+		extras.activity.get_or_insert_with(state.tag_number(), || Activity {
+			entered_at: epoch,
+			last_active: None,
+		})
+	}
+
+	fn entered_at(&self, state: S) -> Duration {
+		self.extras.as_ref().map_or(Duration::ZERO, |extras| {
+			extras.activity.get(state.tag_number()).map_or(extras.epoch, |a| a.entered_at)
+		})
+	}
+
+	fn last_active(&self, state: S) -> Option<S> {
+		self.extras.as_ref()?.activity.get(state.tag_number()).and_then(|a| a.last_active)
+	}
+
+	pub(crate) fn clear_history(&mut self, state: S) {
+		if let Some(activity) = self.extras.as_mut().and_then(|e| e.activity.get_mut(state.tag_number())) {
+			activity.last_active = None;
+		}
+	}
+
+	/// Records the exit of a state, noting its active substate if it has history
+	fn exited(&mut self, state: S, history: History, substate: Option<S>, leaf: S) {
+		match history {
+			History::None => {},
+			History::Shallow => self.activity_mut(state).last_active = substate,
+			History::Deep => self.activity_mut(state).last_active = substate.map(|_| leaf),
+		}
+	}
+
+	fn entered(&mut self, state: S, at: Duration) {
+		self.activity_mut(state).entered_at = at;
+	}
+
+	/// Completes a transition into the given state, replaying any deferred events
+	fn settle(&mut self, state: S) {
+		self.state = state;
+		self.started = true;
+		if let Some(extras) = self.extras.as_mut() {
+			while let Some(event) = extras.deferred.pop_back() {
+				extras.replay.push_front(event);
+			}
+		}
+	}
+
+	pub(crate) fn event_queue(&mut self) -> EventQueue<E> {
+		self.extras_mut().queue.get_or_insert_with(EventQueue::new).clone()
+	}
+
+	pub(crate) fn pending_events(&self) -> usize {
+		self.extras.as_ref().map_or(0, |e| e.replay.len() + e.queue.as_ref().map_or(0, EventQueue::len))
+	}
+
+	pub(crate) fn deferred_events(&self) -> usize {
+		self.extras.as_ref().map_or(0, |e| e.deferred.len())
+	}
+
+	pub(crate) fn set_run_limit(&mut self, run_limit: usize) {
+		self.extras_mut().run_limit = run_limit;
+	}
+
+	/// Records the current state and context, or the state the run will start in if it hasn't
+	pub(crate) fn snapshot<K: EnumTag, A, P, X>(&self, chart: &Chart<S, K, B, A, P, X>) -> Snapshot<S, C>
+	where C: Clone {
+		Snapshot {
+			state: if self.started { self.state } else { chart.resting_state(self.state) },
+			context: self.context.clone(),
+		}
+	}
+
+	/// Restores the current state and context, the state and its ancestors being treated as having
+	/// been entered at the given time
+	pub(crate) fn restore<K: EnumTag, A, P, X>(&mut self, chart: &Chart<S, K, B, A, P, X>, snapshot: Snapshot<S, C>, now: Option<Duration>) -> Result<(), RestoreError<S>> {
+		let state = snapshot.state;
+		if !chart.is_known_state(state) {
+			return Err(RestoreError::UnknownState(state));
+		}
+		if chart.record(state).is_some_and(|r| r.initial.is_some()) {
+			return Err(RestoreError::CompositeState(state));
+		}
+		self.state = state;
+		self.context = snapshot.context;
+		self.started = true;
+		if let Some(now) = now {
+			for s in chart.ancestors(state).filter(|&s| chart.record(s).is_some_and(|r| r.timeout.is_some())) {
+				self.entered(s, now);
+			}
+		}
+		Ok(())
+	}
+
+	/// Finds the earliest due timed transition of the current state and its ancestors, there are
+	/// none without a clock
+	pub(crate) fn next_timeout<K: EnumTag, A, P, X, T: ?Sized>(&self, chart: &Chart<S, K, B, A, P, X>, clock: Option<&T>) -> Option<(S, Duration)> {
+		clock?;
+		let mut next: Option<(S, Duration)> = None;
+		for s in chart.ancestors(self.state) {
+			if let Some(timeout) = chart.record(s).and_then(|r| r.timeout.as_ref()) {
+				let deadline = self.entered_at(s) + timeout.after;
+				if next.is_none_or(|(_, earliest)| deadline < earliest) {
+					next = Some((s, deadline));
+				}
+			}
+		}
+		next
+	}
+
+	/// Steps through entering the initial substates of the current state, unless the run has
+	/// already started
+	fn starting(&self) -> Option<Transit<S>> {
+		if self.started {
+			None
+		} else {
+			Some(Transit::start(self.state))
+		}
+	}
+
+	/// Takes the next deferred or posted event, unless the number already processed has reached the
+	/// run limit
+	fn next_event(&mut self, processed: usize) -> Option<E> {
+		let extras = self.extras.as_mut()?;
+		if processed >= extras.run_limit {
+			return None;
+		}
+		match extras.replay.pop_front() {
+			Some(event) => Some(event),
+			None => extras.queue.as_ref().and_then(EventQueue::pop),
+		}
+	}
+
+	/// Finds the timed transition due at the given time, unless the number already taken has
+	/// reached the run limit
+	fn next_due<K: EnumTag, A, P, X, T: ?Sized>(&self, chart: &Chart<S, K, B, A, P, X>, clock: Option<&T>, now: Duration, taken: usize) -> Option<(S, Duration)> {
+		let run_limit = self.extras.as_ref().map_or(DEFAULT_RUN_LIMIT, |e| e.run_limit);
+		if taken < run_limit {
+			self.next_timeout(chart, clock).filter(|&(_, deadline)| deadline <= now)
+		} else {
+			None
+		}
+	}
+
+	pub(crate) fn start<R: Runner<S, E, C, B>, T: ?Sized + Clock>(&mut self, runner: &mut R, clock: Option<&T>) {
+		self.drive(runner, clock, Drive::new(Goal::Start, None));
+	}
+
+	pub(crate) fn on_event<R: Runner<S, E, C, B>, T: ?Sized + Clock>(&mut self, runner: &mut R, clock: Option<&T>, event: E) -> TransitionOutcome<S> {
+		self.drive(runner, clock, Drive::new(Goal::Event, Some(event))).outcome()
+	}
+
+	pub(crate) fn process_pending<R: Runner<S, E, C, B>, T: ?Sized + Clock>(&mut self, runner: &mut R, clock: Option<&T>) -> usize {
+		self.drive(runner, clock, Drive::new(Goal::Pending, None)).processed()
+	}
+
+	pub(crate) fn advance<R: Runner<S, E, C, B>, T: ?Sized + Clock>(&mut self, runner: &mut R, clock: Option<&T>, now: Duration) -> Vec<TransitionOutcome<S>> {
+		self.drive(runner, clock, Drive::new(Goal::Advance(now), None)).outcomes()
+	}
+
+	/// Runs each Call of the Drive through the Runner until it is done
+	fn drive<R: Runner<S, E, C, B>, T: ?Sized + Clock>(&mut self, runner: &mut R, clock: Option<&T>, mut drive: Drive<S, E>) -> Drive<S, E> {
+		while let Some(call) = drive.next(runner.chart(), self, clock, |event| runner.kind(event)) {
+			match call {
+				Call::Check(index) => {
+					let passed = runner.passes(index, &self.state, drive.event(), &self.context);
+					drive.passed(passed);
+				},
+				Call::Exit(state) => runner.exit(&state, &mut self.context),
+				Call::Act(index, previous) => runner.act(index, &previous, drive.event(), &mut self.context),
+				Call::TimeOut(timed, previous) => runner.time_out(timed, &previous, &mut self.context),
+				Call::Enter(state) => runner.enter(&state, &mut self.context),
+			}
+		}
+		drive
+	}
+}
+
+/// The Goal is what a Drive was asked to do
+#[derive(Copy, Clone)]
+pub(crate) enum Goal {
+	/// enter the initial substates of the state the run was created in, if it hasn't started
+	Start,
+	/// handle the Drive's event, then the posted events
+	Event,
+	/// handle the posted events
+	Pending,
+	/// take the timed transitions due at the given time, handling the posted events after each
+	Advance(Duration),
+}
+
+/// The Call is a predicate or action that a Drive needs its driver to run next, with the Run's
+/// context and, for predicates and transition actions, the Drive's event
+pub(crate) enum Call<S> {
+	/// run the predicate of the transition with the given index in the current state, telling the
+	/// Drive whether it `passed`
+	Check(usize),
+	/// run the exit action of the state
+	Exit(S),
+	/// run the action of the transition with the given index, leaving the given state
+	Act(usize, S),
+	/// run the action of the timed state's timed transition, leaving the given state
+	TimeOut(S, S),
+	/// run the entry action of the state
+	Enter(S),
+}
+
+enum Stage<S> {
+	Start,
+	Idle,
+	Dispatch,
+	Transit(Transit<S>, Act<S>),
+}
+
+/// The Drive decides, one Call at a time, everything a machine does to reach a Goal: which
+/// transition an event takes, whether it is deferred, which posted events and timed transitions
+/// follow and the steps of each transition. Drivers only run the Calls, so machines that call
+/// their closures directly and those that await them behave the same
+pub(crate) struct Drive<S, E> {
+	goal: Goal,
+	stage: Stage<S>,
+	/// the event being handled
+	event: Option<E>,
+	kind: usize,
+	/// the transition whose predicate was last checked, and the state it belongs to
+	cursor: Option<(S, usize)>,
+	answer: Option<bool>,
+	/// whether the outcome of what is being handled is reported
+	report: bool,
+	/// whether posted events are being handled after a timed transition
+	draining: bool,
+	processed: usize,
+	outcomes: Vec<TransitionOutcome<S>>,
+}
+
+impl<S: EnumTag, E> Drive<S, E> {
+	pub(crate) fn new(goal: Goal, event: Option<E>) -> Drive<S, E> {
+		Drive {
+			goal,
+			stage: Stage::Start,
+			event,
+			kind: 0,
+			cursor: None,
+			answer: None,
+			report: true,
+			draining: false,
+			processed: 0,
+			outcomes: Vec::new(),
+		}
+	}
+
+	/// Retrieves the event being handled, for the predicates and actions of its transitions
+	pub(crate) fn event(&self) -> &E {
+		self.event.as_ref().expect("no event is being handled")
+	}
+
+	/// Reports whether the predicate of the last Check passed
+	pub(crate) fn passed(&mut self, passed: bool) {
+		self.answer = Some(passed);
+	}
+
+	/// Retrieves the outcome of an Event
+	pub(crate) fn outcome(mut self) -> TransitionOutcome<S> {
+		self.outcomes.remove(0)
+	}
+
+	/// Retrieves how many posted events were handled by Pending
+	pub(crate) fn processed(&self) -> usize {
+		self.processed
+	}
+
+	/// Retrieves the outcomes of the timed transitions taken by Advance
+	pub(crate) fn outcomes(self) -> Vec<TransitionOutcome<S>> {
+		self.outcomes
+	}
+
+	/// Finds the next Call needed to reach the goal, or None once it is reached. Event kinds are
+	/// found with the given function, as for `Runner::kind`
+	pub(crate) fn next<C, K: EnumTag, B: Storage, A, P, X, T: ?Sized + Clock, F>(&mut self, chart: &Chart<S, K, B, A, P, X>, run: &mut Run<S, E, C, B>, clock: Option<&T>, kind: F) -> Option<Call<S>>
+	where F: Fn(&E) -> Option<usize> {
+		loop {
+			match self.stage {
+				Stage::Start => self.stage = match run.starting() {
+					Some(transit) => Stage::Transit(transit, Act::Start),
+					None => Stage::Idle,
+				},
+				Stage::Transit(ref mut transit, act) => match transit.next(chart, run, clock) {
+					Some(Step::Exit(state)) => return Some(Call::Exit(state)),
+					Some(Step::Act(previous)) => match act {
+						Act::Start => {},
+						Act::Transition(index) => return Some(Call::Act(index, previous)),
+						Act::Timeout(timed) => return Some(Call::TimeOut(timed, previous)),
+					},
+					Some(Step::Enter(state)) => return Some(Call::Enter(state)),
+					None => {
+						if let Act::Start = act {
+							self.stage = Stage::Idle;
+						} else {
+							let outcome = TransitionOutcome::Handled {
+								previous: transit.previous,
+								current: run.state,
+							};
+							self.event = None;
+							self.finish(outcome);
+						}
+					},
+				},
+				Stage::Dispatch => {
+					let state = run.state;
+					if self.answer.take() == Some(true) {
+						let index = self.cursor.take()?.1;
+						self.stage = Stage::Transit(Transit::new(chart, state, chart.actions[index].next_state), Act::Transition(index));
+						continue;
+					}
+					self.cursor = chart.next_offered(state, self.kind, self.cursor);
+					match self.cursor {
+						Some((_, index)) if chart.actions[index].predicate.is_some() => return Some(Call::Check(index)),
+						Some(_) => self.answer = Some(true),
+						None => self.decline(chart, run),
+					}
+				},
+				Stage::Idle => {
+					if let Some(ref event) = self.event {
+						match kind(event) {
+							Some(tag) => {
+								self.kind = tag;
+								self.stage = Stage::Dispatch;
+							},
+							None => {
+								let state = run.state;
+								self.event = None;
+								self.finish(TransitionOutcome::Unhandled {
+									state,
+								});
+							},
+						}
+						continue;
+					}
+					let event = match self.goal {
+						Goal::Start => None,
+						Goal::Event | Goal::Pending => run.next_event(self.processed),
+						Goal::Advance(_) if self.draining => run.next_event(self.processed),
+						Goal::Advance(now) => {
+							let (timed, deadline) = run.next_due(chart, clock, now, self.outcomes.len())?;
+							let state = run.state;
+							self.report = true;
+							match Transit::timeout(chart, state, timed, deadline) {
+								Some(transit) => self.stage = Stage::Transit(transit, Act::Timeout(timed)),
+								None => self.finish(TransitionOutcome::Unhandled {
+									state,
+								}),
+							}
+							continue;
+						},
+					};
+					match event {
+						Some(event) => {
+							self.processed += 1;
+							self.report = false;
+							self.event = Some(event);
+						},
+						None if self.draining => {
+							self.draining = false;
+							self.processed = 0;
+						},
+						None => return None,
+					}
+				},
+			}
+		}
+	}
+
+	/// Handles an event none of whose transitions passed, deferring it if the state defers its kind
+	fn decline<C, K: EnumTag, B: Storage, A, P, X>(&mut self, chart: &Chart<S, K, B, A, P, X>, run: &mut Run<S, E, C, B>) {
+		let state = run.state;
+		let event = self.event.take().expect("no event is being handled");
+		if chart.defers(state, self.kind) {
+			run.extras_mut().deferred.push_back(event);
+			self.finish(TransitionOutcome::Deferred {
+				state,
+			});
+		} else {
+			self.finish(TransitionOutcome::Unhandled {
+				state,
+			});
+		}
+	}
+
+	/// Records the outcome of handling an event or timed transition, then moves on to the posted
+	/// events that follow it
+	fn finish(&mut self, outcome: TransitionOutcome<S>) {
+		if self.report {
+			self.outcomes.push(outcome);
+			self.draining = matches!(self.goal, Goal::Advance(_));
+		}
+		self.stage = Stage::Idle;
+	}
+}
@@ -0,0 +1,58 @@
+#[cfg(not(feature = "std"))]
+use alloc::rc::Rc;
+#[cfg(not(feature = "std"))]
+use core::cell::RefCell;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// The Shared holds a value that all of its clones share. With the `std` feature it is an
+/// `Arc<Mutex>`, so that machines holding one can still be sent between threads, otherwise an
+/// `Rc<RefCell>`
+pub(crate) struct Shared<T> {
+	#[cfg(feature = "std")]
+	value: Arc<Mutex<T>>,
+	#[cfg(not(feature = "std"))]
+	value: Rc<RefCell<T>>,
+}
+
+impl<T> Shared<T> {
+	pub(crate) fn new(value: T) -> Shared<T> {
+		Shared {
+			#[cfg(feature = "std")]
+			value: Arc::new(Mutex::new(value)),
+			#[cfg(not(feature = "std"))]
+			value: Rc::new(RefCell::new(value)),
+		}
+	}
+
+	/// Runs the function with the shared value. A panic while the value was held doesn't stop
+	/// others using it, as the values shared are always left consistent
+	pub(crate) fn with<R, F: FnOnce(&mut T) -> R>(&self, f: F) -> R {
+		#[cfg(feature = "std")]
+		let mut value = self.value.lock().unwrap_or_else(PoisonError::into_inner);
+		#[cfg(not(feature = "std"))]
+		let mut value = self.value.borrow_mut();
+		f(&mut value)
+	}
+}
+
+impl<T> Clone for Shared<T> {
+	fn clone(&self) -> Shared<T> {
+		Shared {
+			value: self.value.clone(),
+		}
+	}
+}
+
+impl<T: Default> Default for Shared<T> {
+	fn default() -> Shared<T> {
+		Shared::new(T::default())
+	}
+}
+
+impl<T: fmt::Debug> fmt::Debug for Shared<T> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		self.with(|value| value.fmt(f))
+	}
+}
@@ -0,0 +1,154 @@
+use core::error::Error;
+use core::fmt;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// The Snapshot records a running Machine's current state and context, with the `serde` feature
+/// it can be serialized to persist the machine and later restore it
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Snapshot<S, C = ()> {
+	/// the state the machine was in
+	pub state: S,
+	/// the machine's context
+	pub context: C,
+}
+
+/// The RestoreError explains why a Snapshot could not be restored into a Machine
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum RestoreError<S> {
+	/// The snapshot's state is not the initial state, and no transition leads to or from it
+	UnknownState(S),
+	/// The snapshot's state has an initial substate, so the machine can never be left in it
+	CompositeState(S),
+}
+
+impl<S: fmt::Debug> fmt::Display for RestoreError<S> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			RestoreError::UnknownState(ref state) =>
+				write!(f, "state {:?} is not part of the machine's transitions", state),
+			RestoreError::CompositeState(ref state) =>
+				write!(f, "state {:?} has an initial substate, so the machine cannot rest in it", state),
+		}
+	}
+}
+
+impl<S: fmt::Debug> Error for RestoreError<S> {}
+
+#[cfg(test)]
+mod test {
+	use super::super::{EnumTag, Machine};
+	use super::*;
+
+	#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+	#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+	enum OrderState {
+		Basket,
+		Checkout,
+		Paying,
+		Confirming,
+		Shipped,
+		Archived,
+	}
+
+	#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+	enum OrderEvent {
+		Checkout,
+		Pay,
+		Confirm,
+	}
+
+	impl EnumTag for OrderState {
+		fn tag_number(&self) -> usize {
+			*self as usize
+		}
+		fn max_tag_number() -> usize {
+			OrderState::Archived as usize
+		}
+	}
+
+	impl EnumTag for OrderEvent {
+		fn tag_number(&self) -> usize {
+			*self as usize
+		}
+		fn max_tag_number() -> usize {
+			OrderEvent::Confirm as usize
+		}
+	}
+
+	#[derive(Clone, Debug, Default, Eq, PartialEq)]
+	#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+	struct Order {
+		items: Vec<String>,
+		attempts: u32,
+	}
+
+	fn order<'a>() -> Machine<'a, OrderState, OrderEvent, Order> {
+		let mut machine = Machine::with_context(OrderState::Basket, Order::default());
+		machine.set_parent(OrderState::Paying, OrderState::Checkout);
+		machine.set_parent(OrderState::Confirming, OrderState::Checkout);
+		machine.set_initial_substate(OrderState::Checkout, OrderState::Paying);
+		machine.add_transition(OrderState::Basket, OrderEvent::Checkout, OrderState::Checkout, |_,_| {});
+		machine.add_transition_with_context(OrderState::Paying, OrderEvent::Pay, OrderState::Confirming, |_, _, order| order.attempts += 1);
+		machine.add_transition(OrderState::Confirming, OrderEvent::Confirm, OrderState::Shipped, |_,_| {});
+		machine
+	}
+
+	#[test]
+	fn test_snapshot_and_restore() {
+		let mut machine = order();
+		machine.context_mut().items.push("book".to_string());
+		machine.on_event(OrderEvent::Checkout);
+		machine.on_event(OrderEvent::Pay);
+		let snapshot = machine.snapshot();
+		assert_eq!(snapshot.state, OrderState::Confirming);
+
+		let mut restored = order();
+		assert_eq!(restored.restore(snapshot), Ok(()));
+		assert_eq!(restored.current_state(), OrderState::Confirming);
+		assert_eq!(restored.context().attempts, 1);
+		assert!(restored.on_event(OrderEvent::Confirm).is_handled());
+	}
+
+	#[test]
+	fn test_restore_validation() {
+		let mut machine = order();
+		assert_eq!(
+			machine.restore(Snapshot { state: OrderState::Archived, context: Order::default() }),
+			Err(RestoreError::UnknownState(OrderState::Archived))
+		);
+		assert_eq!(
+			machine.restore(Snapshot { state: OrderState::Checkout, context: Order::default() }),
+			Err(RestoreError::CompositeState(OrderState::Checkout))
+		);
+		assert_eq!(machine.current_state(), OrderState::Basket);
+		assert_eq!(machine.restore(Snapshot { state: OrderState::Shipped, context: Order::default() }), Ok(()));
+		assert_eq!(
+			RestoreError::UnknownState(OrderState::Archived).to_string(),
+			"state Archived is not part of the machine's transitions"
+		);
+	}
+
+	#[cfg(feature = "serde")]
+	#[test]
+	fn test_serde_round_trip() {
+		let mut machine = order();
+		machine.context_mut().items.push("lamp".to_string());
+		machine.on_event(OrderEvent::Checkout);
+
+		let json = serde_json::to_string(&machine.snapshot()).unwrap();
+		assert_eq!(json, r#"{"state":"Paying","context":{"items":["lamp"],"attempts":0}}"#);
+		let mut restored = order();
+		restored.restore(serde_json::from_str(&json).unwrap()).unwrap();
+		assert_eq!(restored.current_state(), OrderState::Paying);
+		assert_eq!(restored.context().items, ["lamp"]);
+
+		let bytes = bincode::serialize(&machine.snapshot()).unwrap();
+		let mut restored = order();
+		restored.restore(bincode::deserialize(&bytes).unwrap()).unwrap();
+		assert_eq!(restored.current_state(), OrderState::Paying);
+		assert_eq!(*restored.context(), *machine.context());
+	}
+}
@@ -0,0 +1,437 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::collections::HashMap;
+
+/// The Storage is a Machine's storage backend, choosing how the records of its states and its
+/// table of transitions are held. The records are keyed by state tags, the table by pairs of state
+/// and event tags
+pub trait Storage {
+	/// The map used for each of the machine's records
+	type Map<V>: TagMap<V>;
+	/// The table of the machine's transitions
+	type Table<V>: TagTable<V>;
+}
+
+/// The TagMap maps keys, derived from tag numbers, to values
+pub trait TagMap<V> {
+	/// Constructs an empty map
+	fn new() -> Self;
+	/// Retrieves the value with the given key, if there is one
+	fn get(&self, key: usize) -> Option<&V>;
+	/// Retrieves the value with the given key for modification, if there is one
+	fn get_mut(&mut self, key: usize) -> Option<&mut V>;
+	/// Retrieves the value with the given key for modification, first inserting the result of the
+	/// given function if there is no such value
+	fn get_or_insert_with<F: FnOnce() -> V>(&mut self, key: usize, value: F) -> &mut V;
+	/// Iterates over the keys and values, in order of their keys
+	fn iter(&self) -> Box<dyn Iterator<Item = (usize, &V)> + '_>;
+	/// Iterates over the values for modification, in any order
+	fn values_mut(&mut self) -> Box<dyn Iterator<Item = &mut V> + '_>;
+}
+
+/// The TagTable maps pairs of state and event tags to values
+pub trait TagTable<V> {
+	/// Constructs an empty table whose event tags will all be at most `max_event`
+	fn new(max_event: usize) -> Self;
+	/// Retrieves the value for the given state and event tags, if there is one
+	fn get(&self, state: usize, event: usize) -> Option<&V>;
+	/// Sets the value for the given state and event tags, returns false if the table has no room
+	/// for them
+	fn insert(&mut self, state: usize, event: usize, value: V) -> bool;
+	/// Iterates over the event tags and values for the given state tag, in order of event tag
+	fn row(&self, state: usize) -> Box<dyn Iterator<Item = (usize, &V)> + '_>;
+}
+
+/// The DenseStorage keeps room for every key up to the largest it holds, so lookups are a single
+/// index. It suits enums whose tags are small and contiguous, and is what machines use by default
+pub struct DenseStorage;
+
+/// The SortedStorage keeps only the values that exist, in a Vec sorted by key that is searched
+/// with a binary search. It suits enums with large or scattered tags and few transitions
+pub struct SortedStorage;
+
+/// The HashStorage keeps only the values that exist, in a HashMap. It suits enums with large or
+/// scattered tags and many transitions, and is available with the `std` feature
+#[cfg(feature = "std")]
+pub struct HashStorage;
+
+/// The DenseMap is the TagMap of DenseStorage
+pub struct DenseMap<V>(Vec<Option<V>>);
+
+/// The SortedMap is the TagMap of SortedStorage
+pub struct SortedMap<V>(Vec<(usize, V)>);
+
+/// The DenseTable is the TagTable of DenseStorage, keyed by `state * events + event` where
+/// `events` is the number of event tags
+pub struct DenseTable<V> {
+	events: Option<usize>,
+	slots: Vec<Option<V>>,
+}
+
+/// The SortedTable is the TagTable of SortedStorage
+pub struct SortedTable<V>(Vec<((usize, usize), V)>);
+
+impl Storage for DenseStorage {
+	type Map<V> = DenseMap<V>;
+	type Table<V> = DenseTable<V>;
+}
+
+impl Storage for SortedStorage {
+	type Map<V> = SortedMap<V>;
+	type Table<V> = SortedTable<V>;
+}
+
+#[cfg(feature = "std")]
+impl Storage for HashStorage {
+	type Map<V> = HashMap<usize, V>;
+	type Table<V> = HashMap<(usize, usize), V>;
+}
+
+impl<V> TagMap<V> for DenseMap<V> {
+	fn new() -> DenseMap<V> {
+		DenseMap(Vec::new())
+	}
+
+	fn get(&self, key: usize) -> Option<&V> {
+		self.0.get(key).and_then(Option::as_ref)
+	}
+
+	fn get_mut(&mut self, key: usize) -> Option<&mut V> {
+		self.0.get_mut(key).and_then(Option::as_mut)
+	}
+
+	fn get_or_insert_with<F: FnOnce() -> V>(&mut self, key: usize, value: F) -> &mut V {
+		if key >= self.0.len() {
+			self.0.resize_with(key + 1, || None);
+		}
+		self.0[key].get_or_insert_with(value)
+	}
+
+	fn iter(&self) -> Box<dyn Iterator<Item = (usize, &V)> + '_> {
+		Box::new(self.0.iter().enumerate().filter_map(|(key, value)| value.as_ref().map(|v| (key, v))))
+	}
+
+	fn values_mut(&mut self) -> Box<dyn Iterator<Item = &mut V> + '_> {
+		Box::new(self.0.iter_mut().flatten())
+	}
+}
+
+impl<V> TagMap<V> for SortedMap<V> {
+	fn new() -> SortedMap<V> {
+		SortedMap(Vec::new())
+	}
+
+	fn get(&self, key: usize) -> Option<&V> {
+		self.0.binary_search_by_key(&key, |&(k, _)| k).ok().map(|index| &self.0[index].1)
+	}
+
+	fn get_mut(&mut self, key: usize) -> Option<&mut V> {
+		match self.0.binary_search_by_key(&key, |&(k, _)| k) {
+			Ok(index) => Some(&mut self.0[index].1),
+			Err(_) => None,
+		}
+	}
+
+	fn get_or_insert_with<F: FnOnce() -> V>(&mut self, key: usize, value: F) -> &mut V {
+		let index = match self.0.binary_search_by_key(&key, |&(k, _)| k) {
+			Ok(index) => index,
+			Err(index) => {
+				self.0.insert(index, (key, value()));
+				index
+			},
+		};
+		&mut self.0[index].1
+	}
+
+	fn iter(&self) -> Box<dyn Iterator<Item = (usize, &V)> + '_> {
+		Box::new(self.0.iter().map(|(key, value)| (*key, value)))
+	}
+
+	fn values_mut(&mut self) -> Box<dyn Iterator<Item = &mut V> + '_> {
+		Box::new(self.0.iter_mut().map(|(_, value)| value))
+	}
+}
+
+#[cfg(feature = "std")]
+impl<V> TagMap<V> for HashMap<usize, V> {
+	fn new() -> HashMap<usize, V> {
+		HashMap::new()
+	}
+
+	fn get(&self, key: usize) -> Option<&V> {
+		HashMap::get(self, &key)
+	}
+
+	fn get_mut(&mut self, key: usize) -> Option<&mut V> {
+		HashMap::get_mut(self, &key)
+	}
+
+	fn get_or_insert_with<F: FnOnce() -> V>(&mut self, key: usize, value: F) -> &mut V {
+		self.entry(key).or_insert_with(value)
+	}
+
+	fn iter(&self) -> Box<dyn Iterator<Item = (usize, &V)> + '_> {
+		let mut entries: Vec<_> = HashMap::iter(self).map(|(key, value)| (*key, value)).collect();
+		entries.sort_by_key(|&(key, _)| key);
+		Box::new(entries.into_iter())
+	}
+
+	fn values_mut(&mut self) -> Box<dyn Iterator<Item = &mut V> + '_> {
+		Box::new(HashMap::values_mut(self))
+	}
+}
+
+impl<V> DenseTable<V> {
+	/// Finds the slot for the given state and event tags, if it can be addressed
+	fn slot(&self, state: usize, event: usize) -> Option<usize> {
+		let events = self.events?;
+		if event >= events {
+			return None;
+		}
+		state.checked_mul(events)?.checked_add(event)
+	}
+}
+
+impl<V> TagTable<V> for DenseTable<V> {
+	fn new(max_event: usize) -> DenseTable<V> {
+		DenseTable {
+			events: max_event.checked_add(1),
+			slots: Vec::new(),
+		}
+	}
+
+	fn get(&self, state: usize, event: usize) -> Option<&V> {
+		self.slot(state, event).and_then(|slot| self.slots.get(slot)).and_then(Option::as_ref)
+	}
+
+	fn insert(&mut self, state: usize, event: usize, value: V) -> bool {
+		let slot = match self.slot(state, event) {
+			Some(slot) => slot,
+			None => return false,
+		};
+		if slot >= self.slots.len() {
+			if self.slots.try_reserve(slot + 1 - self.slots.len()).is_err() {
+				return false;
+			}
+			self.slots.resize_with(slot + 1, || None);
+		}
+		self.slots[slot] = Some(value);
+		true
+	}
+
+	fn row(&self, state: usize) -> Box<dyn Iterator<Item = (usize, &V)> + '_> {
+		let row = self.slot(state, 0).and_then(|start| self.slots.get(start..)).unwrap_or(&[]);
+		Box::new(row.iter().take(self.events.unwrap_or(0)).enumerate().filter_map(|(event, value)| value.as_ref().map(|v| (event, v))))
+	}
+}
+
+impl<V> SortedTable<V> {
+	fn search(&self, state: usize, event: usize) -> Result<usize, usize> {
+		self.0.binary_search_by_key(&(state, event), |&(key, _)| key)
+	}
+}
+
+impl<V> TagTable<V> for SortedTable<V> {
+	fn new(_max_event: usize) -> SortedTable<V> {
+		SortedTable(Vec::new())
+	}
+
+	fn get(&self, state: usize, event: usize) -> Option<&V> {
+		self.search(state, event).ok().map(|index| &self.0[index].1)
+	}
+
+	fn insert(&mut self, state: usize, event: usize, value: V) -> bool {
+		match self.search(state, event) {
+			Ok(index) => self.0[index].1 = value,
+			Err(index) => self.0.insert(index, ((state, event), value)),
+		}
+		true
+	}
+
+	fn row(&self, state: usize) -> Box<dyn Iterator<Item = (usize, &V)> + '_> {
+		let start = self.0.partition_point(|&((s, _), _)| s < state);
+		Box::new(self.0[start..].iter().take_while(move |&&((s, _), _)| s == state).map(|((_, event), value)| (*event, value)))
+	}
+}
+
+#[cfg(feature = "std")]
+impl<V> TagTable<V> for HashMap<(usize, usize), V> {
+	fn new(_max_event: usize) -> HashMap<(usize, usize), V> {
+		HashMap::new()
+	}
+
+	fn get(&self, state: usize, event: usize) -> Option<&V> {
+		HashMap::get(self, &(state, event))
+	}
+
+	fn insert(&mut self, state: usize, event: usize, value: V) -> bool {
+		HashMap::insert(self, (state, event), value);
+		true
+	}
+
+	fn row(&self, state: usize) -> Box<dyn Iterator<Item = (usize, &V)> + '_> {
+		let mut entries: Vec<_> = self.iter().filter(|&(&(s, _), _)| s == state).map(|(&(_, event), value)| (event, value)).collect();
+		entries.sort_by_key(|&(event, _)| event);
+		Box::new(entries.into_iter())
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::time::Duration;
+
+	use super::*;
+	use super::super::{Clock, EnumTag, History, Machine, ManualClock};
+
+	#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+	enum Register {
+		Status = 0x10,
+		Control = 0x4000,
+		Transmit = 0x4001,
+		Receive = 0x4002,
+		Fault = 0x10_0000,
+	}
+
+	#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+	enum Signal {
+		Start = 0x1,
+		Send = 0x200,
+		Echo = 0x201,
+		Reset = 0x8000,
+	}
+
+	impl EnumTag for Register {
+		fn tag_number(&self) -> usize {
+			*self as usize
+		}
+		fn max_tag_number() -> usize {
+			Register::Fault as usize
+		}
+	}
+
+	impl EnumTag for Signal {
+		fn tag_number(&self) -> usize {
+			*self as usize
+		}
+		fn max_tag_number() -> usize {
+			Signal::Reset as usize
+		}
+	}
+
+	fn run<B: Storage>() {
+		let clock = ManualClock::new();
+		let mut machine: Machine<Register, Signal, Vec<Register>, B> = Machine::with_storage(Register::Status, Vec::new());
+		machine.set_clock(clock.clone());
+		assert!(machine.add_transition(Register::Status, Signal::Start, Register::Control, |_,_| {}));
+		assert!(machine.add_transition(Register::Transmit, Signal::Echo, Register::Receive, |_,_| {}));
+		assert!(machine.add_guarded_transition_with_context(
+			Register::Control, Signal::Reset, Register::Status,
+			|_, _, entered| entered.len() > 2, |_,_,_| {}
+		));
+		assert!(machine.add_transition(Register::Control, Signal::Reset, Register::Fault, |_,_| {}));
+		assert!(!machine.add_transition(Register::Control, Signal::Reset, Register::Status, |_,_| {}));
+		assert!(machine.add_timed_transition(Register::Receive, Duration::from_secs(1), Register::Transmit, |_| {}));
+		machine.set_parent(Register::Transmit, Register::Control);
+		machine.set_parent(Register::Receive, Register::Control);
+		machine.set_initial_substate(Register::Control, Register::Transmit);
+		machine.set_history(Register::Control, History::Shallow);
+		machine.defer_event(Register::Status, Signal::Echo);
+		machine.set_on_enter_with_context(Register::Transmit, |s, entered| entered.push(*s));
+		machine.set_on_enter_with_context(Register::Receive, |s, entered| entered.push(*s));
+
+		assert!(!machine.on_event(Signal::Echo).is_handled());
+		assert_eq!(machine.deferred_events(), 1);
+		assert!(machine.on_event(Signal::Start).is_handled());
+		assert_eq!(machine.current_state(), Register::Receive);
+		clock.advance(Duration::from_secs(1));
+		assert_eq!(machine.advance(clock.now()).len(), 1);
+		assert_eq!(machine.current_state(), Register::Transmit);
+		assert!(machine.on_event(Signal::Reset).is_handled());
+		assert_eq!(machine.current_state(), Register::Status);
+		assert!(!machine.on_event(Signal::Send).is_handled());
+		assert_eq!(machine.context(), &vec![Register::Transmit, Register::Receive, Register::Transmit]);
+		assert!(machine.validate().dead_ends.contains(&Register::Fault));
+	}
+
+	#[test]
+	fn test_sorted_storage() {
+		run::<SortedStorage>();
+	}
+
+	#[test]
+	#[cfg(feature = "std")]
+	fn test_hash_storage() {
+		run::<HashStorage>();
+	}
+
+	#[test]
+	fn test_dense_storage() {
+		let mut map = DenseMap::new();
+		*map.get_or_insert_with(2, || 20) += 1;
+		map.get_or_insert_with(0, || 0);
+		assert_eq!(map.get(2), Some(&21));
+		assert_eq!(map.get(1), None);
+		assert_eq!(map.get(7), None);
+		assert_eq!(map.iter().collect::<Vec<_>>(), vec![(0, &0), (2, &21)]);
+
+		let mut table = DenseTable::new(2);
+		assert!(table.insert(1, 2, 12));
+		assert!(table.insert(2, 0, 20));
+		assert!(!table.insert(0, 3, 3));
+		assert!(!table.insert(usize::MAX, 0, 0));
+		assert_eq!(table.get(1, 2), Some(&12));
+		assert_eq!(table.get(0, 3), None);
+		assert_eq!(table.row(1).collect::<Vec<_>>(), vec![(2, &12)]);
+	}
+
+	#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+	#[repr(u32)]
+	enum Page {
+		Home = 0,
+		Last = 0xFFFF_FFFF,
+	}
+
+	#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+	#[repr(u32)]
+	enum Link {
+		Next = 0,
+		Back = 0xFFFF_FFFF,
+	}
+
+	impl EnumTag for Page {
+		fn tag_number(&self) -> usize {
+			*self as usize
+		}
+		fn max_tag_number() -> usize {
+			Page::Last as usize
+		}
+	}
+
+	impl EnumTag for Link {
+		fn tag_number(&self) -> usize {
+			*self as usize
+		}
+		fn max_tag_number() -> usize {
+			Link::Back as usize
+		}
+	}
+
+	fn large_tags<B: Storage>() {
+		let mut machine: Machine<Page, Link, (), B> = Machine::with_storage(Page::Home, ());
+		assert!(machine.add_transition(Page::Home, Link::Next, Page::Last, |_,_| {}));
+		assert!(machine.add_transition(Page::Last, Link::Back, Page::Home, |_,_| {}));
+		assert!(!machine.on_event(Link::Back).is_handled());
+		assert!(machine.on_event(Link::Next).is_handled());
+		assert!(!machine.on_event(Link::Next).is_handled());
+		assert!(machine.on_event(Link::Back).is_handled());
+		assert_eq!(machine.current_state(), Page::Home);
+	}
+
+	#[test]
+	fn test_large_tags() {
+		large_tags::<SortedStorage>();
+		#[cfg(feature = "std")]
+		large_tags::<HashStorage>();
+	}
+}
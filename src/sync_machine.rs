@@ -0,0 +1,201 @@
+use alloc::boxed::Box;
+#[cfg(feature = "std")]
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use super::{Blocking, Clock, Closures, DenseStorage, StateMachine};
+#[cfg(feature = "std")]
+use super::{EnumTag, EventKind, TransitionOutcome};
+
+/// Sync actions are actions that can be sent to and shared between threads
+pub type SyncAction<'a, S, E, C = ()> = Box<dyn FnMut(&S,&E,&mut C) + Send + Sync + 'a>;
+
+/// Sync predicates are predicates that can be sent to and shared between threads
+pub type SyncPredicate<'a, S, E, C = ()> = Box<dyn Fn(&S,&E,&C) -> bool + Send + Sync + 'a>;
+
+/// Sync state actions are state actions that can be sent to and shared between threads
+pub type SyncStateAction<'a, S, C = ()> = Box<dyn FnMut(&S,&mut C) + Send + Sync + 'a>;
+
+/// The Sendable closures, and the clock timing them, are `Send + Sync`, they are those of a
+/// SyncMachine
+pub enum Sendable {}
+
+impl<'a, S, E, C> Closures<'a, S, E, C> for Sendable {
+	type Action = SyncAction<'a, S, E, C>;
+	type Predicate = SyncPredicate<'a, S, E, C>;
+	type StateAction = SyncStateAction<'a, S, C>;
+	type Clock = dyn Clock + Send + Sync + 'a;
+}
+
+impl Blocking for Sendable {}
+
+/// The SyncMachine is a Machine whose predicates, actions and clock are Send and Sync, so with
+/// the `std` feature the machine itself is Send and Sync whenever its states, events and context
+/// are. It can be moved into a worker thread, or shared between threads with a SharedMachine
+pub type SyncMachine<'a, S, E, C = (), B = DenseStorage> = StateMachine<'a, S, E, C, B, Sendable>;
+
+/// The SharedMachine is a cloneable handle to a SyncMachine that can be given to any number of
+/// threads, events from every thread are handled one at a time in the order they arrive. It is
+/// available with the `std` feature
+#[cfg(feature = "std")]
+pub struct SharedMachine<S: EnumTag, E: EventKind, C = ()> {
+	machine: Arc<Mutex<SyncMachine<'static, S, E, C>>>,
+}
+
+#[cfg(feature = "std")]
+impl<S: EnumTag, E: EventKind, C> SharedMachine<S, E, C> {
+	/// Constructs a new handle taking ownership of the machine
+	pub fn new(machine: SyncMachine<'static, S, E, C>) -> SharedMachine<S, E, C> {
+		SharedMachine {
+			machine: Arc::new(Mutex::new(machine)),
+		}
+	}
+
+	/// Tick the State Machine with an Event, waiting for any event another thread is ticking it
+	/// with to be handled first
+	pub fn on_event(&self, event_type: E) -> TransitionOutcome<S> {
+		self.lock().on_event(event_type)
+	}
+
+	/// Retrieves the current state
+	pub fn current_state(&self) -> S {
+		self.lock().current_state()
+	}
+
+	/// Locks the machine for exclusive use by the calling thread, for example to inspect its
+	/// context, until the returned guard is dropped. An action that panics doesn't stop other
+	/// threads using the machine, which stays in the state the transition was leaving, though any
+	/// actions that ran before the panic keep their effects
+	pub fn lock(&self) -> MutexGuard<'_, SyncMachine<'static, S, E, C>> {
+		self.machine.lock().unwrap_or_else(PoisonError::into_inner)
+	}
+}
+
+#[cfg(feature = "std")]
+impl<S: EnumTag, E: EventKind, C> Clone for SharedMachine<S, E, C> {
+	fn clone(&self) -> SharedMachine<S, E, C> {
+		SharedMachine {
+			machine: self.machine.clone(),
+		}
+	}
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+	use super::*;
+	use super::super::{EnumTag, ManualClock, TransitionOutcome};
+	use std::thread;
+	use std::time::Duration;
+
+	#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+	enum GateState {
+		Closed,
+		Open,
+	}
+
+	#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+	enum GateEvent {
+		Ticket,
+		Pass,
+	}
+
+	impl EnumTag for GateState {
+		fn tag_number(&self) -> usize {
+			*self as usize
+		}
+		fn max_tag_number() -> usize {
+			GateState::Open as usize
+		}
+	}
+
+	impl EnumTag for GateEvent {
+		fn tag_number(&self) -> usize {
+			*self as usize
+		}
+		fn max_tag_number() -> usize {
+			GateEvent::Pass as usize
+		}
+	}
+
+	#[derive(Default)]
+	struct Counters {
+		tickets: usize,
+		passes: usize,
+	}
+
+	fn gate() -> SyncMachine<'static, GateState, GateEvent, Counters> {
+		let mut machine = SyncMachine::with_context(GateState::Closed, Counters::default());
+		machine.add_transition_with_context(GateState::Closed, GateEvent::Ticket, GateState::Open, |_, _, counters| counters.tickets += 1);
+		machine.add_guarded_transition_with_context(
+			GateState::Open, GateEvent::Pass, GateState::Closed,
+			|_, _, counters| counters.tickets > counters.passes, |_, _, counters| counters.passes += 1
+		);
+		machine
+	}
+
+	#[test]
+	fn test_move_to_thread() {
+		let mut machine = gate();
+		let handle = thread::spawn(move || {
+			machine.on_event(GateEvent::Ticket);
+			machine
+		});
+		let mut machine = handle.join().unwrap();
+		assert_eq!(machine.current_state(), GateState::Open);
+		assert!(machine.on_event(GateEvent::Pass).is_handled());
+		assert_eq!(machine.context().passes, 1);
+	}
+
+	#[test]
+	fn test_shared_machine() {
+		let shared = SharedMachine::new(gate());
+		let producers: Vec<_> = (0..4).map(|_| {
+			let shared = shared.clone();
+			thread::spawn(move || {
+				for _ in 0..100 {
+					shared.on_event(GateEvent::Ticket);
+					shared.on_event(GateEvent::Pass);
+				}
+			})
+		}).collect();
+		for producer in producers {
+			producer.join().unwrap();
+		}
+		let machine = shared.lock();
+		assert_eq!(machine.context().tickets, machine.context().passes);
+		assert!(machine.context().tickets > 0);
+		assert_eq!(machine.current_state(), GateState::Closed);
+	}
+
+	#[test]
+	fn test_shared_machine_after_panic() {
+		let mut machine = gate();
+		machine.set_on_enter_with_context(GateState::Open, |_, counters| if counters.tickets == 1 {
+			panic!("jammed");
+		});
+		let shared = SharedMachine::new(machine);
+		let jammed = shared.clone();
+		assert!(thread::spawn(move || jammed.on_event(GateEvent::Ticket)).join().is_err());
+		assert_eq!(shared.current_state(), GateState::Closed);
+		assert!(shared.on_event(GateEvent::Ticket).is_handled());
+		assert!(shared.on_event(GateEvent::Pass).is_handled());
+		assert_eq!(shared.lock().context().passes, 1);
+	}
+
+	#[test]
+	fn test_timed_transition_in_thread() {
+		let clock = ManualClock::new();
+		let mut machine = gate();
+		machine.set_clock(clock.clone());
+		assert!(machine.add_timed_transition(GateState::Open, Duration::from_secs(10), GateState::Closed, |_| {}));
+		machine.event_queue().post(GateEvent::Ticket);
+		let handle = thread::spawn(move || {
+			machine.process_pending();
+			machine
+		});
+		let mut machine = handle.join().unwrap();
+		assert_eq!(machine.current_state(), GateState::Open);
+
+		clock.advance(Duration::from_secs(10));
+		assert_eq!(machine.advance(clock.now()), vec![TransitionOutcome::Handled { previous: GateState::Open, current: GateState::Closed }]);
+	}
+}
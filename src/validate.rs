@@ -0,0 +1,284 @@
+use alloc::collections::BTreeSet;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use super::chart::Chart;
+use super::{Closures, EnumTag, EventKind, StateMachine, Storage};
+
+/// The Validation reports likely mistakes in a Machine, found by walking its transitions from its
+/// initial state. States and events the machine has never been told of can only be reported if
+/// their `EnumTag` implements `variants`, as the derive does, otherwise the validation is marked as
+/// incomplete
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Validation<S, K> {
+	/// states that no sequence of events or timeouts leads to from the initial state
+	pub unreachable_states: Vec<S>,
+	/// states that have no transitions out of them, nor do their ancestors, and that are not
+	/// marked as final
+	pub dead_ends: Vec<S>,
+	/// kinds of event that no state has a transition for
+	pub unhandled_events: Vec<K>,
+	/// false if the states or events don't implement `variants`, in which case unreachable
+	/// states the machine was never told of and unhandled events are missing from the lists
+	pub complete: bool,
+}
+
+impl<S, K> Validation<S, K> {
+	/// Returns true if the validation was complete and no problems were found
+	pub fn is_valid(&self) -> bool {
+		self.complete && self.unreachable_states.is_empty() && self.dead_ends.is_empty() && self.unhandled_events.is_empty()
+	}
+}
+
+impl<'a, S: EnumTag, E: EventKind, C, B: Storage, K: Closures<'a, S, E, C>> StateMachine<'a, S, E, C, B, K> {
+	/// Checks the machine for unreachable states, dead ends and unhandled events, each being
+	/// listed in tag order. Substates are reached through their parent's initial substate or a
+	/// transition straight to them, and a state can use the transitions of its ancestors
+	pub fn validate(&self) -> Validation<S, E::Kind> {
+		self.chart.validate()
+	}
+}
+
+impl<S: EnumTag, K: EnumTag, B: Storage, A, P, X> Chart<S, K, B, A, P, X> {
+	pub(crate) fn validate(&self) -> Validation<S, K> {
+		let mut reachable = BTreeSet::new();
+		let mut rested = BTreeSet::new();
+		let mut pending = vec![self.resting_state(self.initial_state)];
+		while let Some(state) = pending.pop() {
+			if !rested.insert(state.tag_number()) {
+				continue;
+			}
+			let mut current = Some(state);
+			while let Some(active) = current {
+				reachable.insert(active.tag_number());
+				for transition in self.transitions_from(active.tag_number()) {
+					pending.push(self.resting_state(transition.next_state));
+				}
+				if let Some(timeout) = self.record(active).and_then(|r| r.timeout.as_ref()) {
+					pending.push(self.resting_state(timeout.next_state));
+				}
+				current = self.parent(active);
+			}
+		}
+
+		let handled: BTreeSet<usize> = self.actions.iter().map(|transition| transition.event.tag_number()).collect();
+		let describes_states = S::variants().next().is_some();
+		let describes_events = K::variants().next().is_some();
+		let mut unhandled_events: Vec<K> = K::variants().filter(|event| !handled.contains(&event.tag_number())).collect();
+		unhandled_events.sort_by_key(K::tag_number);
+
+		let states = self.states();
+		Validation {
+			unreachable_states: states.iter()
+				.filter(|state| !reachable.contains(&state.tag_number()))
+				.cloned()
+				.collect(),
+			dead_ends: states.iter()
+				.filter(|&&state| self.is_dead_end(state))
+				.cloned()
+				.collect(),
+			unhandled_events,
+			complete: describes_states && describes_events,
+		}
+	}
+
+	fn is_dead_end(&self, state: S) -> bool {
+		if let Some(record) = self.record(state) {
+			if record.is_final || record.initial.is_some() {
+				return false;
+			}
+		}
+		let mut current = Some(state);
+		while let Some(s) = current {
+			let timed = self.record(s).is_some_and(|r| r.timeout.is_some());
+			if timed || self.has_transitions(s.tag_number()) {
+				return false;
+			}
+			current = self.parent(s);
+		}
+		true
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use super::super::{DiagramOptions, Machine, SortedStorage};
+
+	#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+	enum CallState {
+		Idle,
+		Dialling,
+		Ringing,
+		Connected,
+		Talking,
+		OnHold,
+		Failed,
+		HungUp,
+		Voicemail,
+	}
+
+	#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+	enum CallEvent {
+		Dial,
+		Answer,
+		Hold,
+		Resume,
+		HangUp,
+		Transfer,
+	}
+
+	const CALL_STATES: [CallState; 9] = [
+		CallState::Idle, CallState::Dialling, CallState::Ringing, CallState::Connected, CallState::Talking,
+		CallState::OnHold, CallState::Failed, CallState::HungUp, CallState::Voicemail,
+	];
+
+	const CALL_EVENTS: [CallEvent; 6] = [
+		CallEvent::Dial, CallEvent::Answer, CallEvent::Hold, CallEvent::Resume, CallEvent::HangUp, CallEvent::Transfer,
+	];
+
+	impl EnumTag for CallState {
+		fn tag_number(&self) -> usize {
+			*self as usize
+		}
+		fn max_tag_number() -> usize {
+			CallState::Voicemail as usize
+		}
+		fn variants() -> impl Iterator<Item = CallState> {
+			CALL_STATES.into_iter()
+		}
+	}
+
+	impl EnumTag for CallEvent {
+		fn tag_number(&self) -> usize {
+			*self as usize
+		}
+		fn max_tag_number() -> usize {
+			CallEvent::Transfer as usize
+		}
+		fn variants() -> impl Iterator<Item = CallEvent> {
+			CALL_EVENTS.into_iter()
+		}
+	}
+
+	#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+	enum TurnStyleState {
+		Locked,
+		Unlocked,
+	}
+
+	#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+	enum TurnStyleEvent {
+		Push,
+		InsertCoin,
+	}
+
+	impl EnumTag for TurnStyleState {
+		fn tag_number(&self) -> usize {
+			*self as usize
+		}
+		fn max_tag_number() -> usize {
+			TurnStyleState::Unlocked as usize
+		}
+	}
+
+	impl EnumTag for TurnStyleEvent {
+		fn tag_number(&self) -> usize {
+			*self as usize
+		}
+		fn max_tag_number() -> usize {
+			TurnStyleEvent::InsertCoin as usize
+		}
+	}
+
+	/// A register whose tags are addresses spread across the whole of `usize`
+	#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+	enum Register {
+		Status,
+		Control,
+	}
+
+	impl EnumTag for Register {
+		fn tag_number(&self) -> usize {
+			match *self {
+				Register::Status => 0,
+				Register::Control => usize::MAX,
+			}
+		}
+		fn max_tag_number() -> usize {
+			usize::MAX
+		}
+		fn variants() -> impl Iterator<Item = Register> {
+			[Register::Status, Register::Control].into_iter()
+		}
+	}
+
+	fn call<'a>() -> Machine<'a, CallState, CallEvent> {
+		let mut machine = Machine::new(CallState::Idle);
+		machine.add_transition(CallState::Idle, CallEvent::Dial, CallState::Dialling, |_,_| {});
+		machine.add_transition(CallState::Dialling, CallEvent::Answer, CallState::Connected, |_,_| {});
+		machine.add_transition(CallState::Talking, CallEvent::Hold, CallState::OnHold, |_,_| {});
+		machine.add_transition(CallState::OnHold, CallEvent::Resume, CallState::Talking, |_,_| {});
+		machine.add_transition(CallState::Connected, CallEvent::HangUp, CallState::HungUp, |_,_| {});
+		machine.add_transition(CallState::Ringing, CallEvent::Answer, CallState::Connected, |_,_| {});
+		machine.set_parent(CallState::Talking, CallState::Connected);
+		machine.set_parent(CallState::OnHold, CallState::Connected);
+		machine.set_initial_substate(CallState::Connected, CallState::Talking);
+		machine
+	}
+
+	#[test]
+	fn test_validate() {
+		let machine = call();
+		let validation = machine.validate();
+		assert!(!validation.is_valid());
+		assert_eq!(validation.unreachable_states, vec![CallState::Ringing, CallState::Failed, CallState::Voicemail]);
+		assert_eq!(validation.dead_ends, vec![CallState::Failed, CallState::HungUp, CallState::Voicemail]);
+		assert_eq!(validation.unhandled_events, vec![CallEvent::Transfer]);
+	}
+
+	#[test]
+	fn test_validate_from_composite_state() {
+		let mut machine: Machine<CallState, CallEvent> = Machine::new(CallState::Connected);
+		machine.set_parent(CallState::Talking, CallState::Connected);
+		machine.set_parent(CallState::OnHold, CallState::Connected);
+		machine.set_initial_substate(CallState::Connected, CallState::Talking);
+		machine.add_transition(CallState::Talking, CallEvent::Hold, CallState::OnHold, |_,_| {});
+		machine.add_transition(CallState::OnHold, CallEvent::Resume, CallState::Talking, |_,_| {});
+		let unreachable = machine.validate().unreachable_states;
+		assert!(!unreachable.contains(&CallState::Talking));
+		assert!(!unreachable.contains(&CallState::OnHold));
+	}
+
+	#[test]
+	fn test_validate_incomplete() {
+		let mut machine: Machine<TurnStyleState, TurnStyleEvent> = Machine::new(TurnStyleState::Locked);
+		machine.add_transition(TurnStyleState::Locked, TurnStyleEvent::InsertCoin, TurnStyleState::Unlocked, |_,_| {});
+		machine.add_transition(TurnStyleState::Unlocked, TurnStyleEvent::Push, TurnStyleState::Locked, |_,_| {});
+		let validation = machine.validate();
+		assert!(!validation.complete);
+		assert!(!validation.is_valid());
+		assert!(validation.unhandled_events.is_empty());
+		assert!(call().validate().complete);
+	}
+
+	#[test]
+	fn test_validate_large_tags() {
+		let mut machine: Machine<Register, Register, (), SortedStorage> = Machine::with_storage(Register::Status, ());
+		machine.add_transition(Register::Status, Register::Control, Register::Control, |_,_| {});
+		let validation = machine.validate();
+		assert!(validation.complete);
+		assert_eq!(validation.dead_ends, vec![Register::Control]);
+		assert_eq!(validation.unhandled_events, vec![Register::Status]);
+		assert!(machine.to_dot(&DiagramOptions::debug()).contains("s18446744073709551615 [label=\"Control\"];"));
+	}
+
+	#[test]
+	fn test_mark_final() {
+		let mut machine = call();
+		machine.mark_final(CallState::HungUp);
+		assert!(machine.is_final(CallState::HungUp));
+		assert!(!machine.is_final(CallState::Idle));
+		assert_eq!(machine.validate().dead_ends, vec![CallState::Failed, CallState::Voicemail]);
+	}
+}